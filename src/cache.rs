@@ -0,0 +1,136 @@
+//! Persistent cache of content hashes between scans.
+
+use crate::{Hash, HashAlgorithm};
+use std::collections::HashMap;
+
+/// Version of the cache file format, bumped on incompatible changes.
+const CACHE_VERSION: u32 = 1;
+
+/// Size and modification time of a file, used to detect changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Stamp {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+impl Stamp {
+    /// Returns `None` if the modification time is unavailable or predates
+    /// the Unix epoch.
+    pub(crate) fn of(metadata: &std::fs::Metadata) -> Option<Self> {
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some(Self {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    stamp: Stamp,
+    algorithm: HashAlgorithm,
+    hash: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, Entry>,
+}
+
+/// Hashes of previously scanned files, keyed by absolute path.
+///
+/// An entry is only used while the file's size and modification time are
+/// unchanged and it was produced by the requested algorithm.
+pub(crate) struct HashCache {
+    path: std::path::PathBuf,
+    entries: HashMap<String, Entry>,
+}
+
+impl HashCache {
+    /// Loads the cache stored at `path`.
+    ///
+    /// A missing file yields an empty cache. An unreadable or corrupt file is
+    /// reported on stderr and replaced when the cache is saved.
+    pub(crate) fn load(path: &std::path::Path) -> Self {
+        let entries = match std::fs::read(path) {
+            Ok(data) => match serde_json::from_slice::<CacheFile>(&data) {
+                Ok(file) if file.version == CACHE_VERSION => file.entries,
+                Ok(_) => HashMap::new(),
+                Err(e) => {
+                    eprintln!("Warning: ignoring corrupt hash cache {path:?}: {e}");
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                eprintln!("Warning: unable to read hash cache {path:?}: {e}");
+                HashMap::new()
+            }
+        };
+        Self {
+            path: path.to_path_buf(),
+            entries,
+        }
+    }
+
+    /// Returns the cached hash of `path` if it is still valid for `stamp`.
+    pub(crate) fn lookup(
+        &self,
+        path: &std::path::Path,
+        stamp: Stamp,
+        algorithm: HashAlgorithm,
+    ) -> Option<Hash> {
+        let entry = self.entries.get(&cache_key(path)?)?;
+        if entry.stamp != stamp || entry.algorithm != algorithm {
+            return None;
+        }
+        Hash::from_hex(algorithm, &entry.hash)
+    }
+
+    /// Records the hash of `path`, replacing any previous entry.
+    pub(crate) fn insert(&mut self, path: &std::path::Path, stamp: Stamp, hash: Hash) {
+        if let Some(key) = cache_key(path) {
+            self.entries.insert(
+                key,
+                Entry {
+                    stamp,
+                    algorithm: hash.algorithm(),
+                    hash: hash.to_string(),
+                },
+            );
+        }
+    }
+
+    /// Writes the cache back to its file.
+    ///
+    /// The data is written to a temporary file first so that an interrupted
+    /// write does not destroy the previous cache.
+    pub(crate) fn save(self) -> Result<(), std::io::Error> {
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self.entries,
+        };
+        let mut temp_name = self.path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = std::path::PathBuf::from(temp_name);
+        std::fs::write(&temp_path, serde_json::to_vec(&file)?)?;
+        std::fs::rename(&temp_path, &self.path)
+    }
+}
+
+/// Key of a file in the cache, or `None` if its path is not valid UTF-8.
+pub(crate) fn cache_key(path: &std::path::Path) -> Option<String> {
+    std::path::absolute(path)
+        .ok()?
+        .into_os_string()
+        .into_string()
+        .ok()
+}
@@ -0,0 +1,155 @@
+//! Hashes written during a scan, so that an interrupted scan can resume.
+
+use crate::cache::{cache_key, Stamp};
+use crate::{Hash, HashAlgorithm};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Minimum time between two writes of new entries to the checkpoint file.
+const CHECKPOINT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// One hashed file, stored as a line of JSON.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Entry {
+    path: String,
+    #[serde(flatten)]
+    stamp: Stamp,
+    algorithm: HashAlgorithm,
+    hash: String,
+}
+
+struct Writer {
+    file: std::io::BufWriter<std::fs::File>,
+    last_flush: std::time::Instant,
+}
+
+/// Files hashed by an earlier, interrupted run of the same scan, and the file
+/// the hashes of this run are appended to.
+///
+/// Unlike the [`crate::cache::HashCache`], the file is only meant to survive
+/// until the scan completes, and it is written while the scan runs.
+pub(crate) struct Checkpoint {
+    path: std::path::PathBuf,
+    entries: HashMap<String, Entry>,
+    writer: std::sync::Mutex<Option<Writer>>,
+}
+
+impl Checkpoint {
+    /// Loads the entries stored at `path` and opens it for appending.
+    ///
+    /// Lines that cannot be parsed, like one cut off by a crash, are skipped.
+    /// If the file cannot be written, this is reported on stderr and the scan
+    /// goes on without checkpointing.
+    pub(crate) fn open(path: &std::path::Path) -> Self {
+        let mut entries = HashMap::new();
+        match std::fs::read(path) {
+            Ok(data) => {
+                for line in data.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+                    match serde_json::from_slice::<Entry>(line) {
+                        Ok(entry) => {
+                            entries.insert(entry.path.clone(), entry);
+                        }
+                        Err(e) => log::debug!("Skipping checkpoint entry in {path:?}: {e}"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Warning: unable to read checkpoint {path:?}: {e}"),
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path);
+        let writer = match file {
+            Ok(file) => Some(Writer {
+                file: std::io::BufWriter::new(file),
+                last_flush: std::time::Instant::now(),
+            }),
+            Err(e) => {
+                eprintln!("Warning: unable to write checkpoint {path:?}: {e}");
+                None
+            }
+        };
+        Self {
+            path: path.to_path_buf(),
+            entries,
+            writer: std::sync::Mutex::new(writer),
+        }
+    }
+
+    /// Number of entries loaded from an earlier run.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the hash of `path` from an earlier run if it is still valid
+    /// for `stamp`.
+    pub(crate) fn lookup(
+        &self,
+        path: &std::path::Path,
+        stamp: Stamp,
+        algorithm: HashAlgorithm,
+    ) -> Option<Hash> {
+        let entry = self.entries.get(&cache_key(path)?)?;
+        if entry.stamp != stamp || entry.algorithm != algorithm {
+            return None;
+        }
+        Hash::from_hex(algorithm, &entry.hash)
+    }
+
+    /// Appends the hash of `path`. Entries are written to the file at most
+    /// every [`CHECKPOINT_INTERVAL`].
+    pub(crate) fn record(&self, path: &std::path::Path, stamp: Stamp, hash: Hash) {
+        let Some(key) = cache_key(path) else {
+            return;
+        };
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(Writer { file, last_flush }) = writer.as_mut() else {
+            return;
+        };
+        let entry = Entry {
+            path: key,
+            stamp,
+            algorithm: hash.algorithm(),
+            hash: hash.to_string(),
+        };
+        let result = serde_json::to_writer(&mut *file, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|()| file.write_all(b"\n"))
+            .and_then(|()| {
+                if last_flush.elapsed() < CHECKPOINT_INTERVAL {
+                    return Ok(());
+                }
+                *last_flush = std::time::Instant::now();
+                file.flush()
+            });
+        if let Err(e) = result {
+            eprintln!("Warning: unable to write checkpoint {:?}: {e}", self.path);
+            *writer = None;
+        }
+    }
+
+    /// Removes the checkpoint after the scan completed, or writes the
+    /// remaining entries if it did not.
+    pub(crate) fn finish(self, completed: bool) {
+        let writer = self
+            .writer
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(mut writer) = writer else {
+            return;
+        };
+        let result = if completed {
+            drop(writer);
+            std::fs::remove_file(&self.path)
+        } else {
+            writer.file.flush()
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: unable to write checkpoint {:?}: {e}", self.path);
+        }
+    }
+}
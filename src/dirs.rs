@@ -0,0 +1,163 @@
+//! Detection of directories with identical content.
+
+use crate::{FileInfo, Hash, ScanOptions, ScanStats};
+use std::collections::HashMap;
+
+/// Content of a directory collected from the files below it.
+#[derive(Default)]
+struct DirContent {
+    /// Names of the direct children with the digest of their content.
+    entries: Vec<(std::ffi::OsString, Vec<u8>)>,
+    /// Total size of all files below the directory.
+    size: u64,
+    /// Set if a file below the directory has no copy anywhere else, so the
+    /// directory cannot have a copy either.
+    unique: bool,
+}
+
+/// Scans the directory trees below `paths` and returns all groups of two or
+/// more directories with identical content, along with statistics about the
+/// scan.
+///
+/// Two directories are identical if they contain the same names with the
+/// same content, recursively. Only the files considered by `options` are
+/// taken into account; subdirectories without such files are ignored. The
+/// `size` of a group is the total size of the files in one directory.
+///
+/// Groups of subdirectories are left out when their parents are reported
+/// as duplicates themselves. Groups and paths are sorted like in
+/// [`crate::find_duplicates`].
+pub fn find_duplicate_dirs<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let min_copies = options.min_copies();
+    // a hardlink is still part of the directory's content, and any copy of a
+    // file may be part of a copied directory
+    let options = ScanOptions {
+        record_files: true,
+        min_copies: 2,
+        same_dir_only: false,
+        match_ext: false,
+        ignore_hardlinks: false,
+        ..options.clone()
+    };
+    let (file_map, mut stats) = crate::scan_on_directories(paths, &options)?;
+    let files = std::mem::take(&mut stats.files);
+    let (file_groups, stats) = crate::group_duplicates(file_map, stats, &options)?;
+
+    // identify each duplicated file by its hash and, should verifying have
+    // split a hash into several groups, the position among those groups
+    let mut file_digests: HashMap<&std::path::Path, (Vec<u8>, u64)> = HashMap::new();
+    let mut ordinals: HashMap<Hash, u64> = HashMap::new();
+    for (hash, group) in &file_groups {
+        let ordinal = ordinals.entry(*hash).or_default();
+        *ordinal += 1;
+        let ordinal = *ordinal;
+        let mut digest = hash.as_bytes().to_vec();
+        digest.extend_from_slice(&ordinal.to_le_bytes());
+        for path in &group.paths {
+            file_digests.insert(path.as_path(), (digest.clone(), group.size));
+        }
+    }
+
+    let roots: Vec<&std::path::Path> = paths.iter().map(AsRef::as_ref).collect();
+    let mut dirs: HashMap<std::path::PathBuf, DirContent> = HashMap::new();
+    for file in &files {
+        let (Some(parent), Some(name)) = (file.parent(), file.file_name()) else {
+            continue;
+        };
+        let content = dirs.entry(parent.to_path_buf()).or_default();
+        match file_digests.get(file.as_path()) {
+            Some((digest, size)) => {
+                content.entries.push((name.to_owned(), file_entry(digest)));
+                content.size += size;
+            }
+            None => content.unique = true,
+        }
+    }
+
+    // add every directory between the files and their root, deepest first,
+    // so that a directory is complete before it is added to its parent
+    let mut pending: Vec<std::path::PathBuf> = dirs.keys().cloned().collect();
+    let mut index = 0;
+    while let Some(dir) = pending.get(index).cloned() {
+        index += 1;
+        if let Some(parent) = dir.parent().filter(|p| below_root(p, &roots)) {
+            if !dirs.contains_key(parent) {
+                dirs.insert(parent.to_path_buf(), DirContent::default());
+                pending.push(parent.to_path_buf());
+            }
+        }
+    }
+    pending.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    let mut dir_map: HashMap<Hash, FileInfo> = HashMap::new();
+    for dir in pending {
+        let Some(mut content) = dirs.remove(&dir) else {
+            continue;
+        };
+        content.entries.sort();
+        let mut hasher = options.hash_function().hasher();
+        for (name, digest) in &content.entries {
+            let name = name.as_encoded_bytes();
+            hasher.update(&(name.len() as u64).to_le_bytes());
+            hasher.update(name);
+            hasher.update(digest);
+        }
+        let hash = hasher.finalize();
+        if let Some(parent) = dir.parent().filter(|p| below_root(p, &roots)) {
+            if let (Some(parent_content), Some(name)) = (dirs.get_mut(parent), dir.file_name()) {
+                parent_content.size += content.size;
+                parent_content.unique |= content.unique;
+                parent_content
+                    .entries
+                    .push((name.to_owned(), dir_entry(&hash)));
+            }
+        }
+        if content.unique {
+            continue;
+        }
+        match dir_map.get_mut(&hash) {
+            Some(group) => group.add_path(dir),
+            None => {
+                dir_map.insert(hash, FileInfo::new(dir, content.size));
+            }
+        }
+    }
+
+    let mut duplicates: Vec<(Hash, FileInfo)> = dir_map
+        .into_iter()
+        .filter(|(_, group)| group.paths.len() >= min_copies)
+        .collect();
+    let duplicated: std::collections::HashSet<std::path::PathBuf> = duplicates
+        .iter()
+        .flat_map(|(_, group)| group.paths.iter().cloned())
+        .collect();
+    // the copies of a duplicated directory's children are implied
+    duplicates.retain(|(_, group)| {
+        !group
+            .paths
+            .iter()
+            .all(|path| path.parent().is_some_and(|p| duplicated.contains(p)))
+    });
+    crate::sort_groups(&mut duplicates);
+    Ok((duplicates, stats))
+}
+
+/// Whether `dir` is one of `roots` or below one of them.
+fn below_root(dir: &std::path::Path, roots: &[&std::path::Path]) -> bool {
+    roots.iter().any(|root| dir.starts_with(root))
+}
+
+fn file_entry(digest: &[u8]) -> Vec<u8> {
+    let mut entry = vec![b'f'];
+    entry.extend_from_slice(digest);
+    entry
+}
+
+fn dir_entry(hash: &Hash) -> Vec<u8> {
+    let mut entry = vec![b'd'];
+    entry.extend_from_slice(hash.as_bytes());
+    entry
+}
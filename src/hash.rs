@@ -0,0 +1,162 @@
+//! Content hashes and the algorithms producing them.
+
+use sha2::Digest;
+
+/// Largest digest produced by any supported algorithm, in bytes.
+const MAX_DIGEST_LEN: usize = 32;
+
+/// Hash algorithm used to fingerprint file contents.
+#[derive(
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256 (256 bit)
+    #[default]
+    Sha256,
+    /// BLAKE3 (256 bit)
+    Blake3,
+    /// XXH3 (128 bit, non-cryptographic)
+    Xxh3,
+}
+
+/// Source of the hashers used to fingerprint file contents.
+///
+/// Implemented by [`HashAlgorithm`]; other implementations can be set as
+/// [`crate::ScanOptions::hash_function`], e.g. a cheap fake in tests.
+pub trait HashFunction: std::fmt::Debug + Send + Sync {
+    /// Returns a hasher for the content of one file.
+    fn hasher(&self) -> Box<dyn ContentHasher>;
+}
+
+impl HashAlgorithm {
+    /// Length of the digests produced by this algorithm, in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+            HashAlgorithm::Xxh3 => 16,
+        }
+    }
+}
+
+impl HashFunction for HashAlgorithm {
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+        }
+    }
+}
+
+/// Incremental hashing of file contents.
+pub trait ContentHasher {
+    /// Adds the next bytes of the content.
+    fn update(&mut self, data: &[u8]);
+    /// Returns the digest of all bytes added.
+    fn finalize(self: Box<Self>) -> Hash;
+}
+
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(HashAlgorithm::Sha256, &Digest::finalize(*self))
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(
+            HashAlgorithm::Blake3,
+            blake3::Hasher::finalize(&self).as_bytes(),
+        )
+    }
+}
+
+impl ContentHasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(HashAlgorithm::Xxh3, &self.digest128().to_be_bytes())
+    }
+}
+
+/// Digest of a file's content, tagged with the algorithm that produced it.
+///
+/// `Display` formats the digest as lowercase hex.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hash {
+    algorithm: HashAlgorithm,
+    len: u8,
+    hash: [u8; MAX_DIGEST_LEN],
+}
+
+impl Hash {
+    /// Wraps a digest produced by `algorithm`, or by a [`HashFunction`] that
+    /// chooses to tag its digests with it.
+    ///
+    /// # Panics
+    ///
+    /// If `digest` is longer than 32 bytes.
+    pub fn new(algorithm: HashAlgorithm, digest: &[u8]) -> Self {
+        assert!(digest.len() <= MAX_DIGEST_LEN, "digest too long");
+        let mut hash = [0u8; MAX_DIGEST_LEN];
+        hash[..digest.len()].copy_from_slice(digest);
+        Self {
+            algorithm,
+            len: digest.len() as u8,
+            hash,
+        }
+    }
+
+    /// Parses a digest formatted by `Display`.
+    pub fn from_hex(algorithm: HashAlgorithm, hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(2) || hex.len() > 2 * MAX_DIGEST_LEN {
+            return None;
+        }
+        let digest = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Self::new(algorithm, &digest))
+    }
+
+    /// Algorithm that produced this digest.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Raw bytes of the digest.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.hash[..self.len as usize]
+    }
+}
+
+impl std::fmt::Display for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        for b in self.as_bytes() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
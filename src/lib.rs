@@ -0,0 +1,2022 @@
+//! Detection of duplicate files in a directory tree.
+//!
+//! Files are first grouped by size, then every file whose size is shared with
+//! another file is hashed (with SHA-256 unless another [`HashAlgorithm`] is
+//! selected). Files with equal hashes are reported as duplicates.
+
+mod cache;
+mod checkpoint;
+mod dirs;
+mod hash;
+mod progress;
+mod text;
+
+use cache::HashCache;
+use checkpoint::Checkpoint;
+pub use dirs::find_duplicate_dirs;
+pub use hash::{ContentHasher, Hash, HashAlgorithm, HashFunction};
+use progress::Progress;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+};
+
+/// Default size of the buffer files are read into for hashing.
+pub const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Smallest accepted read buffer size, see `ScanOptions::chunk_size`.
+pub const MIN_CHUNK_SIZE: usize = 512;
+
+/// Largest accepted read buffer size, see `ScanOptions::chunk_size`.
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Names of system files and tool directories skipped unless
+/// `ScanOptions::default_excludes` is unset. Their copies are rarely worth
+/// reporting.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "Thumbs.db",
+    ".DS_Store",
+    "desktop.ini",
+    ".git",
+    "node_modules",
+];
+
+/// Name of the files holding exclude patterns for the directory they are in,
+/// see `ScanOptions::ignore_files`.
+pub const IGNORE_FILE_NAME: &str = ".fdcignore";
+
+/// Number of bytes at the start of a file hashed to quickly rule out
+/// duplicates before hashing the whole file.
+const PREHASH_SIZE: u64 = 4 * 1024;
+
+/// Smallest file that is memory-mapped for hashing, see `ScanOptions::mmap`.
+pub const MMAP_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Delay before the first retry of a failed read, doubled for each further one.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Settings controlling which files are considered during a scan.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Number of worker threads used for hashing.
+    pub jobs: usize,
+    /// Files smaller than this many bytes are skipped.
+    pub min_size: Option<u64>,
+    /// Files larger than this many bytes are skipped.
+    pub max_size: Option<u64>,
+    /// Confirm that files sharing a hash are byte-for-byte identical.
+    pub verify: bool,
+    /// Follow symbolic links instead of skipping them.
+    pub follow_symlinks: bool,
+    /// Maximum number of symbolic links followed along a single path below
+    /// the root. Entries beyond the limit are skipped with a warning.
+    pub max_symlinks: usize,
+    /// Treat hardlinks to the same inode as a single file (Unix only).
+    pub ignore_hardlinks: bool,
+    /// Ignore zero-length files, which would otherwise all be reported as
+    /// duplicates of each other.
+    pub skip_empty: bool,
+    /// Include hidden files and directories, i.e. names starting with a dot
+    /// on Unix or entries with the hidden attribute on Windows.
+    pub hidden: bool,
+    /// Abort on the first unreadable file or directory instead of skipping it.
+    pub strict: bool,
+    /// Show the number of scanned files and hashed bytes on stderr.
+    pub progress: bool,
+    /// Algorithm used to hash file contents.
+    pub hash: HashAlgorithm,
+    /// Glob patterns of files and directories to skip, matched against the
+    /// path relative to the scan root. Patterns without a `/` match an entry
+    /// of that name at any depth.
+    pub exclude: Vec<String>,
+    /// Also skip the entries named in [`DEFAULT_EXCLUDES`].
+    pub default_excludes: bool,
+    /// Read exclude patterns from a [`IGNORE_FILE_NAME`] file in each
+    /// directory, one per line. They work like `exclude` relative to that
+    /// directory and apply to everything below it. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub ignore_files: bool,
+    /// Only consider files with one of these extensions (case-insensitive,
+    /// without the leading dot). All files are considered if empty.
+    pub extensions: Vec<String>,
+    /// Maximum depth of directories to descend into. `Some(0)` only scans
+    /// files directly inside the root.
+    pub max_depth: Option<usize>,
+    /// Only treat files in the same directory as duplicates of each other.
+    /// Groups spanning several directories are split per directory.
+    pub same_dir_only: bool,
+    /// Only treat files with the same extension (case-insensitive) as
+    /// duplicates of each other. Groups are split per extension.
+    pub match_ext: bool,
+    /// Size of the buffer files are read into for hashing and verifying.
+    /// Values outside `MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE` are clamped.
+    pub chunk_size: usize,
+    /// Do not descend into directories on a different file system than the
+    /// scan root. Has no effect on platforms without device ids.
+    pub one_file_system: bool,
+    /// Only consider files last modified at least this long ago.
+    pub older_than: Option<std::time::Duration>,
+    /// File storing hashes between runs, so that unchanged files (same path,
+    /// size and modification time) are not hashed again.
+    pub cache: Option<std::path::PathBuf>,
+    /// File the hashes are periodically written to while scanning, so that a
+    /// scan interrupted by a crash or Ctrl-C can be resumed without hashing
+    /// the unchanged files again. It is removed once the scan completes.
+    pub checkpoint: Option<std::path::PathBuf>,
+    /// Record every file passing the filters in `ScanStats::files`.
+    pub record_files: bool,
+    /// Record the zero-length files encountered in `ScanStats::empty_files`,
+    /// independently of `skip_empty`.
+    pub collect_empty: bool,
+    /// How often hashing a file is retried after an error that may be
+    /// transient, e.g. on network file systems.
+    pub retries: usize,
+    /// Only consider a random fraction (between 0 and 1) of the file sizes,
+    /// for a quick estimate. Files of one size are either all considered or
+    /// all skipped, so the groups found are complete and their reclaimable
+    /// space can be scaled up by `1 / sample`.
+    pub sample: Option<f64>,
+    /// Hash files of at least [`MMAP_MIN_SIZE`] through a memory map instead
+    /// of reading them, falling back to reading if mapping fails. Only
+    /// supported on Unix. A file truncated while it is hashed this way
+    /// crashes the process.
+    pub mmap: bool,
+    /// Hash every file in full, including those of a unique size, so that
+    /// the result also contains groups with a single path.
+    pub hash_all: bool,
+    /// Only report groups of at least this many files. Values below 2 are
+    /// treated as 2.
+    pub min_copies: usize,
+    /// Hash function used instead of `hash`, e.g. a fake one in tests. The
+    /// hash cache and the checkpoint are not used with it.
+    pub hash_function: Option<std::sync::Arc<dyn HashFunction>>,
+    /// Hash text files with unified line endings and without trailing
+    /// whitespace, so that files differing only in those group together.
+    /// Files containing a NUL byte are hashed as they are. As files of
+    /// different sizes may match, every file is hashed in full, `verify` is
+    /// ignored and the hash cache and the checkpoint are not used. Groups of
+    /// files that are not byte-identical are listed in `ScanStats::normalized`.
+    pub normalize_text: bool,
+    /// Only hash the first and the last this many bytes of larger files,
+    /// together with their size. This is much faster for large media files,
+    /// but files differing in between are taken as duplicates: unless
+    /// `verify` compares them in full, groups of such files are listed in
+    /// `ScanStats::likely`. The hash cache and the checkpoint are not used.
+    pub partial_hash: Option<u64>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            jobs: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            min_size: None,
+            max_size: None,
+            verify: false,
+            follow_symlinks: false,
+            max_symlinks: 40,
+            ignore_hardlinks: true,
+            skip_empty: true,
+            hidden: false,
+            strict: false,
+            progress: false,
+            hash: HashAlgorithm::default(),
+            exclude: Vec::new(),
+            default_excludes: true,
+            ignore_files: true,
+            extensions: Vec::new(),
+            max_depth: None,
+            same_dir_only: false,
+            match_ext: false,
+            one_file_system: false,
+            chunk_size: HASH_CHUNK_SIZE,
+            older_than: None,
+            cache: None,
+            checkpoint: None,
+            collect_empty: false,
+            record_files: false,
+            retries: 2,
+            sample: None,
+            mmap: false,
+            hash_all: false,
+            min_copies: 2,
+            hash_function: None,
+            normalize_text: false,
+            partial_hash: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn chunk_size(&self) -> usize {
+        self.chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+    }
+
+    fn hash_function(&self) -> &dyn HashFunction {
+        match &self.hash_function {
+            Some(function) => function.as_ref(),
+            None => &self.hash,
+        }
+    }
+
+    fn min_copies(&self) -> usize {
+        self.min_copies.max(2)
+    }
+
+    /// Number of bytes hashed at each end of a file of `size` bytes, if it
+    /// is only hashed partially.
+    fn partial_len(&self, size: u64) -> Option<u64> {
+        self.partial_hash.filter(|&len| size > 2 * len)
+    }
+
+    fn size_in_range(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    fn extension_allowed(&self, path: &std::path::Path) -> bool {
+        self.extensions.is_empty()
+            || path.extension().is_some_and(|ext| {
+                self.extensions
+                    .iter()
+                    .any(|allowed| ext.eq_ignore_ascii_case(allowed.trim_start_matches('.')))
+            })
+    }
+}
+
+/// Compiles a single exclude pattern as described on [`ScanOptions::exclude`].
+pub fn exclude_glob(pattern: &str) -> Result<globset::Glob, globset::Error> {
+    let pattern = if pattern.contains('/') {
+        pattern.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{pattern}")
+    };
+    globset::GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+}
+
+fn build_exclude_set(options: &ScanOptions) -> Result<globset::GlobSet, std::io::Error> {
+    let mut builder = globset::GlobSetBuilder::new();
+    let defaults = DEFAULT_EXCLUDES.iter().filter(|_| options.default_excludes);
+    for pattern in options
+        .exclude
+        .iter()
+        .map(String::as_str)
+        .chain(defaults.copied())
+    {
+        builder.add(
+            exclude_glob(pattern)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        );
+    }
+    builder
+        .build()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+/// Counters collected during a scan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Files and directories skipped because they could not be read.
+    pub errors: Vec<ScanError>,
+    /// Number of regular files encountered while walking the tree.
+    pub files_scanned: u64,
+    /// Total number of bytes read for hashing.
+    pub bytes_hashed: u64,
+    /// Number of files that were deleted or changed their size while the
+    /// scan was running and were therefore skipped.
+    pub vanished: usize,
+    /// Zero-length files found, if `ScanOptions::collect_empty` is set.
+    pub empty_files: Vec<std::path::PathBuf>,
+    /// Files passing the filters, if `ScanOptions::record_files` is set.
+    pub files: Vec<std::path::PathBuf>,
+    /// Hashes of the groups whose files only match after normalizing their
+    /// text, if `ScanOptions::normalize_text` is set.
+    pub normalized: HashSet<Hash>,
+    /// Hashes of the groups whose files were only hashed partially and not
+    /// compared in full, if `ScanOptions::partial_hash` is set.
+    pub likely: HashSet<Hash>,
+}
+
+/// A file or directory that was skipped because of an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    pub path: std::path::PathBuf,
+    pub message: String,
+}
+
+/// Error raised when a file's size differs from the one seen while walking
+/// the tree.
+#[derive(Debug)]
+struct FileChanged;
+
+impl std::fmt::Display for FileChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "file changed during the scan")
+    }
+}
+
+impl std::error::Error for FileChanged {}
+
+fn file_changed_error() -> std::io::Error {
+    std::io::Error::other(FileChanged)
+}
+
+/// Whether `e` means that the entry disappeared or changed after it was listed.
+fn is_vanished(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::NotFound
+        || e.get_ref().is_some_and(|inner| inner.is::<FileChanged>())
+}
+
+/// Whether retrying cannot help with `e`.
+fn is_permanent(e: &std::io::Error) -> bool {
+    is_vanished(e)
+        || matches!(
+            e.kind(),
+            std::io::ErrorKind::PermissionDenied
+                | std::io::ErrorKind::InvalidInput
+                | std::io::ErrorKind::Unsupported
+                | std::io::ErrorKind::IsADirectory
+        )
+}
+
+/// Runs `read` until it succeeds, fails with a permanent error or has been
+/// retried `retries` times, waiting longer before each retry.
+fn with_retries<T>(
+    path: &std::path::Path,
+    retries: usize,
+    mut read: impl FnMut() -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    let mut delay = RETRY_DELAY;
+    for _ in 0..retries {
+        match read() {
+            Err(e) if !is_permanent(&e) => {
+                log::debug!("Retrying {path:?} in {delay:?}: {e}");
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    read()
+}
+
+/// Records an error on a single entry in `stats` unless the scan is strict.
+/// Entries that vanished or changed during the scan are always skipped, and
+/// recorded as well.
+///
+/// Returns `Ok(None)` if the entry should be skipped.
+fn tolerate<T>(
+    result: Result<T, std::io::Error>,
+    path: &std::path::Path,
+    options: &ScanOptions,
+    stats: &mut ScanStats,
+) -> Result<Option<T>, std::io::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if is_vanished(&e) => {
+            log::debug!("Skipping {path:?}: {e}");
+            stats.vanished += 1;
+            stats.errors.push(ScanError {
+                path: path.to_path_buf(),
+                message: "vanished or changed during the scan".to_string(),
+            });
+            Ok(None)
+        }
+        Err(e) if !options.strict => {
+            log::debug!("Skipping {path:?}: {e}");
+            stats.errors.push(ScanError {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            });
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Scans the directory tree below `path` and hashes every file that could
+/// have a duplicate.
+///
+/// The returned map contains one entry per distinct content hash. Files whose
+/// size is unique in the tree are never hashed and therefore not included.
+///
+/// Unless `options.strict` is set, unreadable entries below `path` are
+/// collected in [`ScanStats::errors`] instead of aborting the scan. Failing
+/// to read `path` itself is always an error.
+pub fn scan_on_directory(
+    path: &std::path::Path,
+    options: &ScanOptions,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    scan_on_directories(&[path], options)
+}
+
+/// Like [`scan_on_directory`], but scans several directory trees into a
+/// single map so that duplicates spanning different roots are detected.
+///
+/// A root that lies inside another given root is skipped, since its files
+/// would otherwise be reported as duplicates of themselves.
+pub fn scan_on_directories<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    let exclude = build_exclude_set(options)?;
+    let roots: Vec<&std::path::Path> = paths.iter().map(AsRef::as_ref).collect();
+    scan_with(
+        |state| walk_roots(&roots, options, state),
+        exclude,
+        options,
+        None,
+    )
+}
+
+/// Like [`scan_on_directory`], but hashes exactly the given files instead of
+/// walking a directory tree.
+///
+/// Paths that are missing or not regular files are skipped and recorded in
+/// [`ScanStats::errors`], or abort the scan if `options.strict` is set.
+pub fn scan_files(
+    files: &[std::path::PathBuf],
+    options: &ScanOptions,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    scan_with(
+        |state| walk_list(files, options, state),
+        globset::GlobSet::empty(),
+        options,
+        None,
+    )
+}
+
+/// Runs the scan pipeline with the given walker and the cache, checkpoint and
+/// progress display requested by `options`.
+fn scan_with(
+    walk: impl FnOnce(&mut ScanState) -> Result<(), std::io::Error> + Send,
+    exclude: globset::GlobSet,
+    options: &ScanOptions,
+    completed: Option<&mut GroupsCompleted>,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    let progress = Progress::new(options.progress);
+    // stored hashes cannot be told apart from those of another function
+    let builtin = options.hash_function.is_none()
+        && !options.normalize_text
+        && options.partial_hash.is_none();
+    let mut cache = options
+        .cache
+        .as_deref()
+        .filter(|_| builtin)
+        .map(HashCache::load);
+    let checkpoint = options
+        .checkpoint
+        .as_deref()
+        .filter(|_| builtin)
+        .map(Checkpoint::open);
+    if let Some(checkpoint) = checkpoint.as_ref().filter(|c| c.len() > 0) {
+        log::info!(
+            "Resuming with {} hashes from the checkpoint",
+            checkpoint.len()
+        );
+    }
+    // groups passed on during the scan may be printed right away
+    let mut clearing;
+    let completed = match completed {
+        Some(completed) => {
+            clearing = |groups: Vec<(Hash, FileInfo)>| {
+                if groups.iter().any(|(_, v)| v.paths.len() > 1) {
+                    progress.clear();
+                }
+                completed(groups)
+            };
+            Some(&mut clearing as &mut GroupsCompleted)
+        }
+        None => None,
+    };
+    let result = scan_pipeline(
+        walk,
+        exclude,
+        options,
+        cache.as_mut(),
+        checkpoint.as_ref(),
+        &progress,
+        completed,
+    );
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish(result.is_ok());
+    }
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save() {
+            eprintln!("Warning: unable to write hash cache: {e}");
+        }
+    }
+    progress.finish();
+    let (file_map, mut stats) = result?;
+    stats.files_scanned = progress.total_files_scanned();
+    stats.bytes_hashed = progress.total_bytes_hashed();
+    Ok((file_map, stats))
+}
+
+/// Walks every root that is not covered by another one, passing the
+/// candidate files to `state.found`.
+fn walk_roots(
+    paths: &[&std::path::Path],
+    options: &ScanOptions,
+    state: &mut ScanState,
+) -> Result<(), std::io::Error> {
+    let canonical_roots = paths
+        .iter()
+        .map(std::fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    for (index, &path) in paths.iter().enumerate() {
+        let root = &canonical_roots[index];
+        if let Some(outer) = canonical_roots
+            .iter()
+            .enumerate()
+            .find(|(other, outer)| {
+                *other != index && root.starts_with(outer) && (root != *outer || *other < index)
+            })
+            .map(|(other, _)| paths[other])
+        {
+            eprintln!("Warning: skipping {path:?}, it is already covered by {outer:?}");
+            continue;
+        }
+        state.root = path.to_path_buf();
+        if options.follow_symlinks || options.one_file_system {
+            let metadata = std::fs::metadata(long_path(path))?;
+            if options.follow_symlinks {
+                state.visited_dirs.insert(dir_id(path, &metadata)?);
+            }
+            if options.one_file_system {
+                state.root_device = inode_id(&metadata).map(|(device, _)| device);
+                if state.root_device.is_none() {
+                    eprintln!("Warning: device ids are unavailable, scanning across file systems");
+                }
+            }
+        }
+        let result = scan_rec(path, std::fs::read_dir(long_path(path))?, 0, options, state);
+        state.ignores.clear();
+        result?;
+    }
+    Ok(())
+}
+
+/// Reads the patterns of the ignore file in `dir`, if there is one.
+///
+/// Invalid patterns are skipped with a warning.
+fn read_ignore_file(
+    dir: &std::path::Path,
+    options: &ScanOptions,
+    stats: &mut ScanStats,
+) -> Result<Option<globset::GlobSet>, std::io::Error> {
+    let path = dir.join(IGNORE_FILE_NAME);
+    let content = match std::fs::read_to_string(long_path(&path)) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        result => match tolerate(result, &path, options, stats)? {
+            Some(content) => content,
+            None => return Ok(None),
+        },
+    };
+    let mut builder = globset::GlobSetBuilder::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match exclude_glob(line) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => eprintln!("Warning: ignoring pattern {line:?} in {path:?}: {e}"),
+        }
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+/// Identifies a directory independently of the path used to reach it.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = std::path::PathBuf;
+
+#[cfg(unix)]
+fn dir_id(_path: &std::path::Path, metadata: &std::fs::Metadata) -> Result<DirId, std::io::Error> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &std::path::Path, _metadata: &std::fs::Metadata) -> Result<DirId, std::io::Error> {
+    std::fs::canonicalize(path)
+}
+
+/// Returns `path` in a form that file system calls accept regardless of its
+/// length.
+///
+/// On Windows this is the extended-length (`\\?\`) form of the absolute
+/// path, which is not subject to the `MAX_PATH` limit. Paths shown to the
+/// user keep their original form.
+#[cfg(windows)]
+fn long_path(path: &std::path::Path) -> std::borrow::Cow<'_, std::path::Path> {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Component, Prefix};
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.into();
+    };
+    let (prefix, skip) = match absolute.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => (r"\\?\", 0),
+            // `\\server\share` becomes `\\?\UNC\server\share`
+            Prefix::UNC(..) => (r"\\?\UNC\", 2),
+            _ => return absolute.into(),
+        },
+        _ => return absolute.into(),
+    };
+    let mut wide: Vec<u16> = prefix.encode_utf16().collect();
+    wide.extend(absolute.as_os_str().encode_wide().skip(skip));
+    std::path::PathBuf::from(std::ffi::OsString::from_wide(&wide)).into()
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &std::path::Path) -> std::borrow::Cow<'_, std::path::Path> {
+    path.into()
+}
+
+/// Device and inode number of a file, if the platform provides them.
+#[cfg(unix)]
+fn inode_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Describes a file that is neither a regular file nor a directory.
+#[cfg(unix)]
+fn special_file_kind(file_type: &std::fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        "named pipe"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() {
+        "block device"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else {
+        "special file"
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_kind(_file_type: &std::fs::FileType) -> &'static str {
+    "special file"
+}
+
+#[cfg(windows)]
+fn is_hidden(entry: &std::fs::DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    entry
+        .metadata()
+        .is_ok_and(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn is_hidden(entry: &std::fs::DirEntry) -> bool {
+    entry.file_name().as_encoded_bytes().starts_with(b".")
+}
+
+/// Bookkeeping accumulated while walking the directory tree.
+struct ScanState<'a> {
+    /// Directory the scan started from.
+    root: std::path::PathBuf,
+    exclude: globset::GlobSet,
+    /// Patterns of the ignore files in the directories being scanned, with
+    /// the directory they are relative to.
+    ignores: Vec<(std::path::PathBuf, globset::GlobSet)>,
+    /// Directories already entered, used to break symlink cycles.
+    visited_dirs: HashSet<DirId>,
+    /// Inodes of files already collected, used to collapse hardlinks.
+    seen_inodes: HashSet<(u64, u64)>,
+    /// Files modified after this time are skipped.
+    modified_cutoff: Option<std::time::SystemTime>,
+    /// Device of the current root if the scan stays on its file system.
+    root_device: Option<u64>,
+    /// Symbolic links followed to reach the directory being scanned.
+    symlinks_followed: usize,
+    /// Randomly keyed hasher picking the file sizes of `ScanOptions::sample`,
+    /// with the largest hash that is picked.
+    sampler: Option<(std::collections::hash_map::RandomState, u64)>,
+    stats: ScanStats,
+    progress: &'a Progress,
+    /// Set when the scan failed elsewhere and walking should stop.
+    cancelled: &'a std::sync::atomic::AtomicBool,
+    /// Receives each candidate file with its size.
+    found: &'a mut dyn FnMut(std::path::PathBuf, u64),
+}
+
+impl<'a> ScanState<'a> {
+    fn new(
+        exclude: globset::GlobSet,
+        options: &ScanOptions,
+        progress: &'a Progress,
+        cancelled: &'a std::sync::atomic::AtomicBool,
+        found: &'a mut dyn FnMut(std::path::PathBuf, u64),
+    ) -> Self {
+        Self {
+            root: std::path::PathBuf::new(),
+            exclude,
+            ignores: Vec::new(),
+            visited_dirs: HashSet::new(),
+            seen_inodes: HashSet::new(),
+            modified_cutoff: options.older_than.map(|age| {
+                std::time::SystemTime::now()
+                    .checked_sub(age)
+                    .unwrap_or(std::time::UNIX_EPOCH)
+            }),
+            root_device: None,
+            symlinks_followed: 0,
+            sampler: options.sample.map(|fraction| {
+                let threshold = (fraction.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+                (std::collections::hash_map::RandomState::new(), threshold)
+            }),
+            stats: ScanStats::default(),
+            progress,
+            cancelled,
+            found,
+        }
+    }
+}
+
+/// Collects the files listed by `reader` for directory `dir`, which is
+/// `depth` levels below the scan root, and descends into its subdirectories.
+fn scan_rec(
+    dir: &std::path::Path,
+    reader: std::fs::ReadDir,
+    depth: usize,
+    options: &ScanOptions,
+    state: &mut ScanState,
+) -> Result<(), std::io::Error> {
+    if options.ignore_files {
+        if let Some(ignore) = read_ignore_file(dir, options, &mut state.stats)? {
+            state.ignores.push((dir.to_path_buf(), ignore));
+        }
+    }
+    for it in reader {
+        if state.cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        let Some(entry) = tolerate(it, dir, options, &mut state.stats)? else {
+            continue;
+        };
+        // not `entry.path()`, which would carry the extended-length prefix
+        let path = dir.join(entry.file_name());
+        if state
+            .exclude
+            .is_match(path.strip_prefix(&state.root).unwrap_or(&path))
+        {
+            log::debug!("Skipping {path:?}: excluded");
+            continue;
+        }
+        if let Some((base, _)) = state
+            .ignores
+            .iter()
+            .find(|(base, ignore)| ignore.is_match(path.strip_prefix(base).unwrap_or(&path)))
+        {
+            log::debug!(
+                "Skipping {path:?}: excluded by {:?}",
+                base.join(IGNORE_FILE_NAME)
+            );
+            continue;
+        }
+        if !options.hidden && is_hidden(&entry) {
+            log::debug!("Skipping {path:?}: hidden");
+            continue;
+        }
+        let Some(file_type) = tolerate(entry.file_type(), &path, options, &mut state.stats)? else {
+            continue;
+        };
+        let metadata = if file_type.is_symlink() {
+            if !options.follow_symlinks {
+                log::debug!("Skipping {path:?}: symbolic link");
+                continue;
+            }
+            if state.symlinks_followed >= options.max_symlinks {
+                log::warn!(
+                    "Skipping {path:?}: more than {} symbolic links followed",
+                    options.max_symlinks
+                );
+                continue;
+            }
+            match std::fs::metadata(long_path(&path)) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    log::debug!("Skipping {path:?}: broken symbolic link");
+                    state.stats.errors.push(ScanError {
+                        path,
+                        message: format!("broken symbolic link: {e}"),
+                    });
+                    continue;
+                }
+            }
+        } else {
+            match tolerate(entry.metadata(), &path, options, &mut state.stats)? {
+                Some(metadata) => metadata,
+                None => continue,
+            }
+        };
+        if metadata.is_dir() {
+            if options
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+            {
+                log::debug!("Skipping {path:?}: maximum depth reached");
+                continue;
+            }
+            if let Some(device) = state.root_device {
+                if inode_id(&metadata).is_some_and(|(other, _)| other != device) {
+                    log::debug!("Skipping {path:?}: on a different file system");
+                    continue;
+                }
+            }
+            if options.follow_symlinks {
+                let Some(id) =
+                    tolerate(dir_id(&path, &metadata), &path, options, &mut state.stats)?
+                else {
+                    continue;
+                };
+                if !state.visited_dirs.insert(id) {
+                    log::debug!("Skipping {path:?}: directory already visited");
+                    continue;
+                }
+            }
+            if let Some(reader) = tolerate(
+                std::fs::read_dir(long_path(&path)),
+                &path,
+                options,
+                &mut state.stats,
+            )? {
+                log::debug!("Entering directory {path:?}");
+                let followed = usize::from(file_type.is_symlink());
+                state.symlinks_followed += followed;
+                let ignores = state.ignores.len();
+                let result = scan_rec(&path, reader, depth + 1, options, state);
+                state.ignores.truncate(ignores);
+                state.symlinks_followed -= followed;
+                result?;
+            }
+        } else if metadata.is_file() {
+            consider_file(path, &metadata, options, state);
+        } else {
+            // reading a fifo or a device could block or never end
+            eprintln!(
+                "Warning: skipping {path:?}: {}, not a regular file",
+                special_file_kind(&metadata.file_type())
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Passes a regular file to `state.found` unless the options filter it out.
+fn consider_file(
+    path: std::path::PathBuf,
+    metadata: &std::fs::Metadata,
+    options: &ScanOptions,
+    state: &mut ScanState,
+) {
+    state.progress.file_scanned(metadata.len());
+    if !options.extension_allowed(&path) {
+        log::debug!("Skipping {path:?}: extension not selected");
+        return;
+    }
+    if let Some(cutoff) = state.modified_cutoff {
+        if metadata
+            .modified()
+            .map_or(true, |modified| modified > cutoff)
+        {
+            log::debug!("Skipping {path:?}: modified too recently");
+            return;
+        }
+    }
+    let size = metadata.len();
+    if let Some((sampler, threshold)) = &state.sampler {
+        if std::hash::BuildHasher::hash_one(sampler, size) > *threshold {
+            log::debug!("Skipping {path:?}: size not sampled");
+            return;
+        }
+    }
+    if size == 0 && options.collect_empty {
+        state.stats.empty_files.push(path.clone());
+    }
+    if size == 0 && options.skip_empty {
+        log::debug!("Skipping {path:?}: empty file");
+        return;
+    }
+    if options.ignore_hardlinks {
+        if let Some(id) = inode_id(metadata) {
+            if !state.seen_inodes.insert(id) {
+                log::debug!("Skipping {path:?}: hardlink to a file already seen");
+                return;
+            }
+        }
+    }
+    if options.size_in_range(size) {
+        if options.record_files {
+            state.stats.files.push(path.clone());
+        }
+        (state.found)(path, size);
+    } else {
+        log::debug!("Skipping {path:?}: size {size} out of range");
+    }
+}
+
+/// Passes the regular files among `files` to `state.found`.
+///
+/// Paths that do not exist or are not regular files are skipped with an
+/// error, as they cannot be hashed.
+fn walk_list(
+    files: &[std::path::PathBuf],
+    options: &ScanOptions,
+    state: &mut ScanState,
+) -> Result<(), std::io::Error> {
+    for path in files {
+        if state.cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+            break;
+        }
+        let metadata = std::fs::metadata(long_path(path)).and_then(|metadata| {
+            if metadata.is_file() {
+                Ok(metadata)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "not a regular file",
+                ))
+            }
+        });
+        // a missing file is an error here, not a file that vanished
+        let metadata = match metadata {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            )),
+            metadata => metadata,
+        };
+        if let Some(metadata) = tolerate(metadata, path, options, &mut state.stats)? {
+            consider_file(path.clone(), &metadata, options, state);
+        }
+    }
+    Ok(())
+}
+
+/// Work item for the hashing workers, with the file's size from the walk.
+enum Job {
+    /// Hash the first [`PREHASH_SIZE`] bytes of the file.
+    Prefix(std::path::PathBuf, u64),
+    /// Hash the whole file.
+    Full(std::path::PathBuf, u64),
+}
+
+/// Messages received by the collector from the walker and the workers.
+enum Event {
+    /// The walker found a file that may have duplicates.
+    Found(std::path::PathBuf, u64),
+    /// The walker is done, with the counters it collected.
+    WalkDone(Result<ScanStats, std::io::Error>),
+    Prefix(std::path::PathBuf, u64, Result<Hash, std::io::Error>),
+    Full(
+        std::path::PathBuf,
+        u64,
+        Result<(Hash, Option<cache::Stamp>), std::io::Error>,
+    ),
+}
+
+/// Runs `walk` to find the candidate files, hashes them and groups them by
+/// hash.
+///
+/// Walking, reading and hashing overlap: a walker thread streams the files it
+/// finds to a collector on the calling thread, which hands them to
+/// `options.jobs` workers as soon as a second file of the same size shows up.
+/// Files of unique size are never hashed, unless `options.hash_all` asks for
+/// every file to be hashed right away. Files larger than [`PREHASH_SIZE`]
+/// are first compared by a hash of their beginning, and only fully hashed if
+/// that partial hash is shared as well. Full hashes are looked up in `cache`
+/// and `checkpoint` if given, and newly computed ones are added to them.
+///
+/// If `completed` is given, the files of each size are passed to it grouped
+/// by hash as soon as all of them are hashed, instead of being returned.
+fn scan_pipeline(
+    walk: impl FnOnce(&mut ScanState) -> Result<(), std::io::Error> + Send,
+    exclude: globset::GlobSet,
+    options: &ScanOptions,
+    cache: Option<&mut HashCache>,
+    checkpoint: Option<&Checkpoint>,
+    progress: &Progress,
+    completed: Option<&mut GroupsCompleted>,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    let cancelled = std::sync::atomic::AtomicBool::new(false);
+    let (job_sender, job_receiver) = std::sync::mpsc::channel::<Job>();
+    let job_receiver = std::sync::Mutex::new(job_receiver);
+    let (event_sender, events) = std::sync::mpsc::channel::<Event>();
+    let cached = cache.as_deref();
+    let normalized = text::NormalizedText(options.hash_function());
+    let function: &dyn HashFunction = if options.normalize_text {
+        &normalized
+    } else {
+        options.hash_function()
+    };
+    let full_hash = |path: &std::path::Path, size: u64| {
+        let hash = || {
+            with_retries(path, options.retries, || {
+                if let Some(len) = options.partial_len(size) {
+                    return hash_file_ends(path, function, size, len, progress);
+                }
+                hash_file(
+                    path,
+                    function,
+                    size,
+                    options.chunk_size(),
+                    options.mmap,
+                    progress,
+                )
+            })
+        };
+        if cached.is_none() && checkpoint.is_none() {
+            return hash().map(|hash| (hash, None));
+        }
+        // take the stamp before reading, so that a concurrent change
+        // invalidates the entry on the next run
+        let metadata = std::fs::metadata(long_path(path))?;
+        if metadata.len() != size {
+            return Err(file_changed_error());
+        }
+        let Some(stamp) = cache::Stamp::of(&metadata) else {
+            return hash().map(|hash| (hash, None));
+        };
+        if let Some(hash) = cached.and_then(|cache| cache.lookup(path, stamp, options.hash)) {
+            progress.bytes_skipped(size);
+            return Ok((hash, None));
+        }
+        // the cache has yet to learn about hashes from the checkpoint
+        let new_entry = cached.map(|_| stamp);
+        if let Some(hash) = checkpoint.and_then(|c| c.lookup(path, stamp, options.hash)) {
+            progress.bytes_skipped(size);
+            return Ok((hash, new_entry));
+        }
+        let hash = hash()?;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.record(path, stamp, hash);
+        }
+        Ok((hash, new_entry))
+    };
+    let (result, new_cache_entries) = std::thread::scope(|scope| {
+        let walker_events = event_sender.clone();
+        let cancelled = &cancelled;
+        scope.spawn(move || {
+            let mut found = |path, size| {
+                let _ = walker_events.send(Event::Found(path, size));
+            };
+            let mut state = ScanState::new(exclude, options, progress, cancelled, &mut found);
+            let result = walk(&mut state).map(|()| state.stats);
+            let _ = walker_events.send(Event::WalkDone(result));
+        });
+        for _ in 0..options.jobs.max(1) {
+            let events = event_sender.clone();
+            let job_receiver = &job_receiver;
+            let full_hash = &full_hash;
+            scope.spawn(move || loop {
+                let job = job_receiver
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .recv();
+                let Ok(job) = job else {
+                    break;
+                };
+                if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+                    continue;
+                }
+                let event = match job {
+                    Job::Prefix(path, size) => {
+                        let result = with_retries(&path, options.retries, || {
+                            hash_file_prefix(&path, options.hash_function(), PREHASH_SIZE, progress)
+                        });
+                        Event::Prefix(path, size, result)
+                    }
+                    Job::Full(path, size) => {
+                        let result = full_hash(&path, size);
+                        Event::Full(path, size, result)
+                    }
+                };
+                if events.send(event).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(event_sender);
+        let mut new_cache_entries = Vec::new();
+        let result = collect_hashes(
+            events,
+            job_sender,
+            options,
+            progress,
+            &mut new_cache_entries,
+            completed,
+        );
+        if result.is_err() {
+            // make the walker and the workers stop early
+            cancelled.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        (result, new_cache_entries)
+    });
+    if let Some(cache) = cache {
+        for (path, stamp, hash) in new_cache_entries {
+            cache.insert(&path, stamp, hash);
+        }
+    }
+    result
+}
+
+/// Runs the collector side of [`scan_pipeline`] until the walk is done and
+/// every dispatched job has been answered.
+fn collect_hashes(
+    events: std::sync::mpsc::Receiver<Event>,
+    jobs: std::sync::mpsc::Sender<Job>,
+    options: &ScanOptions,
+    progress: &Progress,
+    new_cache_entries: &mut Vec<(std::path::PathBuf, cache::Stamp, Hash)>,
+    mut completed: Option<&mut GroupsCompleted>,
+) -> Result<(HashMap<Hash, FileInfo>, ScanStats), std::io::Error> {
+    let mut stats = ScanStats::default();
+    // first file of each size or prefix hash, until a second one shows up
+    let mut size_map: HashMap<u64, Option<std::path::PathBuf>> = HashMap::new();
+    let mut prefix_map: HashMap<(u64, Hash), Option<std::path::PathBuf>> = HashMap::new();
+    let mut file_map: HashMap<Hash, FileInfo> = HashMap::new();
+    // jobs still running and hashes found for each size, to tell when the
+    // files of a size are complete
+    let mut pending_per_size: HashMap<u64, usize> = HashMap::new();
+    let mut hashes_per_size: HashMap<u64, Vec<Hash>> = HashMap::new();
+    let mut pending = 0usize;
+    let mut walking = true;
+    // the groups of a size are only complete at the end if files of other
+    // sizes can join them
+    let incremental = !options.normalize_text;
+    let dispatch = |job: Job, pending: &mut usize, pending_per_size: &mut HashMap<u64, usize>| {
+        *pending += 1;
+        let (Job::Prefix(_, size) | Job::Full(_, size)) = job;
+        progress.bytes_queued(match job {
+            Job::Prefix(..) => size.min(PREHASH_SIZE),
+            Job::Full(..) => options.partial_len(size).map_or(size, |len| 2 * len),
+        });
+        *pending_per_size.entry(size).or_default() += 1;
+        // the workers only stop once this sender is dropped
+        let _ = jobs.send(job);
+    };
+    let mut complete = |size: u64,
+                        file_map: &mut HashMap<Hash, FileInfo>,
+                        hashes_per_size: &mut HashMap<u64, Vec<Hash>>| {
+        let Some(completed) = completed.as_mut() else {
+            return;
+        };
+        let groups: Vec<(Hash, FileInfo)> = hashes_per_size
+            .remove(&size)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hash| file_map.remove(&hash).map(|v| (hash, v)))
+            .collect();
+        if !groups.is_empty() {
+            completed(groups);
+        }
+    };
+    while walking || pending > 0 {
+        let Ok(event) = events.recv() else {
+            break;
+        };
+        match event {
+            Event::Found(path, size) => {
+                let job = |path| {
+                    if size > PREHASH_SIZE {
+                        Job::Prefix(path, size)
+                    } else {
+                        Job::Full(path, size)
+                    }
+                };
+                // normalized text files of different sizes may match
+                if options.hash_all || options.normalize_text {
+                    dispatch(Job::Full(path, size), &mut pending, &mut pending_per_size);
+                    continue;
+                }
+                match size_map.entry(size) {
+                    std::collections::hash_map::Entry::Vacant(e) => {
+                        e.insert(Some(path));
+                    }
+                    std::collections::hash_map::Entry::Occupied(mut e) => {
+                        if let Some(first) = e.get_mut().take() {
+                            dispatch(job(first), &mut pending, &mut pending_per_size);
+                        }
+                        dispatch(job(path), &mut pending, &mut pending_per_size);
+                    }
+                }
+            }
+            Event::WalkDone(result) => {
+                walking = false;
+                progress.walk_done();
+                let walk_stats = result?;
+                stats.errors.extend(walk_stats.errors);
+                stats.vanished += walk_stats.vanished;
+                stats.empty_files.extend(walk_stats.empty_files);
+                stats.files.extend(walk_stats.files);
+                // no more files can join the sizes hashed so far
+                let sizes: Vec<u64> = hashes_per_size
+                    .keys()
+                    .filter(|_| incremental)
+                    .filter(|size| !pending_per_size.contains_key(size))
+                    .copied()
+                    .collect();
+                for size in sizes {
+                    complete(size, &mut file_map, &mut hashes_per_size);
+                }
+            }
+            Event::Prefix(path, size, result) => {
+                pending -= 1;
+                if let Some(hash) = tolerate(result, &path, options, &mut stats)? {
+                    match prefix_map.entry((size, hash)) {
+                        std::collections::hash_map::Entry::Vacant(e) => {
+                            e.insert(Some(path));
+                        }
+                        std::collections::hash_map::Entry::Occupied(mut e) => {
+                            if let Some(first) = e.get_mut().take() {
+                                dispatch(
+                                    Job::Full(first, size),
+                                    &mut pending,
+                                    &mut pending_per_size,
+                                );
+                            }
+                            dispatch(Job::Full(path, size), &mut pending, &mut pending_per_size);
+                        }
+                    }
+                }
+                if job_done(size, &mut pending_per_size) && !walking && incremental {
+                    complete(size, &mut file_map, &mut hashes_per_size);
+                }
+            }
+            Event::Full(path, size, result) => {
+                pending -= 1;
+                if let Some((hash, stamp)) = tolerate(result, &path, options, &mut stats)? {
+                    log::trace!("Hashed {path:?} ({size} bytes): {hash}");
+                    if let Some(stamp) = stamp {
+                        new_cache_entries.push((path.clone(), stamp, hash));
+                    }
+                    match file_map.entry(hash) {
+                        std::collections::hash_map::Entry::Occupied(mut e) => {
+                            let info = e.get_mut();
+                            info.size = info.size.min(size);
+                            info.add_path(path)
+                        }
+                        std::collections::hash_map::Entry::Vacant(e) => {
+                            e.insert(FileInfo::new(path, size));
+                            hashes_per_size.entry(size).or_default().push(hash);
+                        }
+                    }
+                }
+                if job_done(size, &mut pending_per_size) && !walking && incremental {
+                    complete(size, &mut file_map, &mut hashes_per_size);
+                }
+            }
+        }
+    }
+    // only left if the events ended early
+    let sizes: Vec<u64> = hashes_per_size.keys().copied().collect();
+    for size in sizes {
+        complete(size, &mut file_map, &mut hashes_per_size);
+    }
+    if log::log_enabled!(log::Level::Trace) {
+        for (size, path) in size_map {
+            if let Some(path) = path {
+                log::trace!("Not hashing {path:?}: no other file of size {size}");
+            }
+        }
+        for (_, path) in prefix_map {
+            if let Some(path) = path {
+                log::trace!("Not hashing {path:?}: beginning differs from other files");
+            }
+        }
+    }
+    Ok((file_map, stats))
+}
+
+/// Receives the files of one size grouped by hash once all of them are hashed.
+type GroupsCompleted<'a> = dyn FnMut(Vec<(Hash, FileInfo)>) + 'a;
+
+/// Counts a finished job for files of `size` and returns whether none of
+/// them is left.
+fn job_done(size: u64, pending_per_size: &mut HashMap<u64, usize>) -> bool {
+    let Some(count) = pending_per_size.get_mut(&size) else {
+        return true;
+    };
+    *count -= 1;
+    if *count > 0 {
+        return false;
+    }
+    pending_per_size.remove(&size);
+    true
+}
+
+/// A set of files sharing the same content hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    /// Paths of all files with this content. Groups returned by the
+    /// `find_duplicates` functions list them in lexicographic order.
+    pub paths: Vec<std::path::PathBuf>,
+    /// Size of each file in bytes. Files matching as normalized text may
+    /// differ in size; the group then has the size of its smallest file.
+    pub size: u64,
+}
+
+impl FileInfo {
+    fn new(path: std::path::PathBuf, size: u64) -> Self {
+        Self {
+            paths: Vec::from(&[path]),
+            size,
+        }
+    }
+
+    fn add_path(&mut self, path: std::path::PathBuf) {
+        self.paths.push(path);
+    }
+}
+
+/// Hashes the whole content of a file.
+///
+/// The file's size is not derived from the bytes read here; groups use the
+/// size from the metadata collected while walking the tree. If the file no
+/// longer has `expected_size` bytes, hashing fails as the file changed.
+///
+/// With `mmap`, large files are hashed through a memory map if possible.
+fn hash_file(
+    path: &std::path::Path,
+    function: &dyn HashFunction,
+    expected_size: u64,
+    chunk_size: usize,
+    mmap: bool,
+    progress: &Progress,
+) -> Result<Hash, std::io::Error> {
+    let mut hasher = function.hasher();
+    let mut file = std::fs::File::open(long_path(path))?;
+    if mmap && expected_size >= MMAP_MIN_SIZE {
+        if file.metadata()?.len() != expected_size {
+            return Err(file_changed_error());
+        }
+        match hash_mapped(&file, expected_size, chunk_size, hasher.as_mut(), progress) {
+            Ok(()) => return Ok(hasher.finalize()),
+            Err(e) => {
+                log::debug!("Reading {path:?} instead of mapping it: {e}");
+                hasher = function.hasher();
+            }
+        }
+    }
+    let mut buffer = vec![0u8; chunk_size];
+    let mut size = 0;
+    loop {
+        let bytes_read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..bytes_read]);
+        progress.bytes_hashed(bytes_read as u64);
+        size += bytes_read as u64;
+    }
+    if size != expected_size {
+        return Err(file_changed_error());
+    }
+    Ok(hasher.finalize())
+}
+
+/// Feeds the first `len` bytes of `file` to `hasher` through a read-only
+/// memory map, `chunk_size` bytes at a time.
+#[cfg(unix)]
+fn hash_mapped(
+    file: &std::fs::File,
+    len: u64,
+    chunk_size: usize,
+    hasher: &mut dyn hash::ContentHasher,
+    progress: &Progress,
+) -> Result<(), std::io::Error> {
+    use std::os::fd::AsRawFd;
+    let len = usize::try_from(len)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: a fresh private read-only mapping of an open file, which is
+    // only read while it is mapped and unmapped before returning
+    unsafe {
+        let data = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        if data == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        libc::madvise(data, len, libc::MADV_SEQUENTIAL);
+        for chunk in std::slice::from_raw_parts(data as *const u8, len).chunks(chunk_size) {
+            hasher.update(chunk);
+            progress.bytes_hashed(chunk.len() as u64);
+        }
+        libc::munmap(data, len);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn hash_mapped(
+    _file: &std::fs::File,
+    _len: u64,
+    _chunk_size: usize,
+    _hasher: &mut dyn hash::ContentHasher,
+    _progress: &Progress,
+) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "memory maps are not supported on this platform",
+    ))
+}
+
+/// Hashes the first `len` bytes of a file.
+fn hash_file_prefix(
+    path: &std::path::Path,
+    function: &dyn HashFunction,
+    len: u64,
+    progress: &Progress,
+) -> Result<Hash, std::io::Error> {
+    let mut hasher = function.hasher();
+    let mut file = std::fs::File::open(long_path(path))?;
+    let mut buffer = vec![0u8; len as usize];
+    let bytes_read = read_chunk(&mut file, &mut buffer)?;
+    hasher.update(&buffer[..bytes_read]);
+    progress.bytes_hashed(bytes_read as u64);
+    Ok(hasher.finalize())
+}
+
+/// Hashes the first and the last `len` bytes of a file of `expected_size`
+/// bytes, followed by its size.
+fn hash_file_ends(
+    path: &std::path::Path,
+    function: &dyn HashFunction,
+    expected_size: u64,
+    len: u64,
+    progress: &Progress,
+) -> Result<Hash, std::io::Error> {
+    use std::io::Seek;
+    let mut hasher = function.hasher();
+    let mut file = std::fs::File::open(long_path(path))?;
+    if file.metadata()?.len() != expected_size {
+        return Err(file_changed_error());
+    }
+    let mut buffer = vec![0u8; len as usize];
+    for offset in [0, expected_size - len] {
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        if read_chunk(&mut file, &mut buffer)? != buffer.len() {
+            return Err(file_changed_error());
+        }
+        hasher.update(&buffer);
+        progress.bytes_hashed(len);
+    }
+    hasher.update(&expected_size.to_le_bytes());
+    Ok(hasher.finalize())
+}
+
+/// Reads from `file` until `buffer` is full or the end of the file is reached.
+fn read_chunk(file: &mut std::fs::File, buffer: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Compares two files byte by byte, stopping at the first difference.
+pub fn files_equal(
+    a: &std::path::Path,
+    b: &std::path::Path,
+    chunk_size: usize,
+) -> Result<bool, std::io::Error> {
+    let mut file_a = std::fs::File::open(long_path(a))?;
+    let mut file_b = std::fs::File::open(long_path(b))?;
+    let mut buffer_a = vec![0u8; chunk_size];
+    let mut buffer_b = vec![0u8; chunk_size];
+    loop {
+        let read_a = read_chunk(&mut file_a, &mut buffer_a)?;
+        let read_b = read_chunk(&mut file_b, &mut buffer_b)?;
+        if buffer_a[..read_a] != buffer_b[..read_b] {
+            return Ok(false);
+        }
+        if read_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Whether the files at `paths`, which share a normalized hash, are also
+/// byte-identical. Binary files were hashed as they are, so only text files
+/// are compared. Unreadable files count as different.
+fn byte_identical(paths: &[std::path::PathBuf], chunk_size: usize) -> bool {
+    if text::is_text_file(&paths[0]).is_ok_and(|text| !text) {
+        return true;
+    }
+    let size = |path: &std::path::Path| std::fs::metadata(long_path(path)).map(|m| m.len()).ok();
+    let first_size = size(&paths[0]);
+    paths[1..].iter().all(|path| {
+        first_size.is_some()
+            && size(path) == first_size
+            && files_equal(&paths[0], path, chunk_size).unwrap_or(false)
+    })
+}
+
+/// Splits a group of files sharing a hash into groups of byte-identical files.
+fn verify_group(
+    v: FileInfo,
+    options: &ScanOptions,
+    stats: &mut ScanStats,
+) -> Result<Vec<FileInfo>, std::io::Error> {
+    let mut groups: Vec<FileInfo> = Vec::new();
+    'paths: for path in v.paths {
+        for group in groups.iter_mut() {
+            let equal = files_equal(&group.paths[0], &path, options.chunk_size());
+            match tolerate(equal, &path, options, stats)? {
+                Some(true) => {
+                    group.add_path(path);
+                    continue 'paths;
+                }
+                Some(false) => {}
+                None => continue 'paths,
+            }
+        }
+        groups.push(FileInfo::new(path, v.size));
+    }
+    Ok(groups)
+}
+
+/// Splits a group of files into one group per parent directory.
+fn split_by_directory(v: FileInfo) -> Vec<FileInfo> {
+    split_by(v, |path| path.parent().map(std::path::Path::to_path_buf))
+}
+
+/// Splits a group of files into one group per extension, ignoring case.
+fn split_by_extension(v: FileInfo) -> Vec<FileInfo> {
+    split_by(v, |path| {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    })
+}
+
+/// Splits a group of files into groups of the paths with equal `key`.
+fn split_by<K: PartialEq>(v: FileInfo, key: impl Fn(&std::path::Path) -> K) -> Vec<FileInfo> {
+    let mut groups: Vec<(K, FileInfo)> = Vec::new();
+    for path in v.paths {
+        let path_key = key(&path);
+        match groups.iter_mut().find(|(k, _)| *k == path_key) {
+            Some((_, group)) => group.add_path(path),
+            None => groups.push((path_key, FileInfo::new(path, v.size))),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Scans the directory tree below `path` and returns all groups of two or
+/// more files with identical content, along with statistics about the scan.
+///
+/// When `options.verify` is set, files sharing a hash are additionally
+/// compared byte by byte and split into separate groups if they differ.
+///
+/// Groups are ordered by descending file size, then by hash, and the paths
+/// within each group are sorted, so that the result is stable across runs.
+pub fn find_duplicates(
+    path: &std::path::Path,
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    find_duplicates_in(&[path], options)
+}
+
+/// Like [`find_duplicates`], but searches several directory trees at once.
+/// Groups may contain paths from different roots.
+pub fn find_duplicates_in<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let (file_map, stats) = scan_on_directories(paths, options)?;
+    group_duplicates(file_map, stats, options)
+}
+
+/// Like [`find_duplicates`], but only compares the given files with each
+/// other instead of walking a directory tree. See [`scan_files`].
+pub fn find_duplicates_among(
+    files: &[std::path::PathBuf],
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let (file_map, stats) = scan_files(files, options)?;
+    group_duplicates(file_map, stats, options)
+}
+
+/// Turns the scanned files into sorted groups of duplicates, verifying and
+/// splitting them as requested by `options`.
+pub(crate) fn group_duplicates(
+    file_map: HashMap<Hash, FileInfo>,
+    mut stats: ScanStats,
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let mut duplicates = Vec::new();
+    for (k, v) in file_map {
+        duplicates.extend(finish_group(&k, v, options, &mut stats)?.map(|v| (k, v)));
+    }
+    sort_groups(&mut duplicates);
+    Ok((duplicates, stats))
+}
+
+/// Verifies and splits the files sharing one hash as requested by
+/// `options`, and returns the resulting groups of at least
+/// `options.min_copies` files.
+fn finish_group(
+    hash: &Hash,
+    v: FileInfo,
+    options: &ScanOptions,
+    stats: &mut ScanStats,
+) -> Result<impl Iterator<Item = FileInfo>, std::io::Error> {
+    let mut groups = if v.paths.len() < options.min_copies() {
+        Vec::new()
+    } else if options.normalize_text {
+        if !byte_identical(&v.paths, options.chunk_size()) {
+            stats.normalized.insert(*hash);
+        }
+        vec![v]
+    } else if options.verify {
+        verify_group(v, options, stats)?
+    } else {
+        if options.partial_len(v.size).is_some() {
+            stats.likely.insert(*hash);
+        }
+        vec![v]
+    };
+    if options.same_dir_only {
+        groups = groups.into_iter().flat_map(split_by_directory).collect();
+    }
+    if options.match_ext {
+        groups = groups.into_iter().flat_map(split_by_extension).collect();
+    }
+    let min_copies = options.min_copies();
+    Ok(groups
+        .into_iter()
+        .filter(move |v| v.paths.len() >= min_copies))
+}
+
+/// A group of two or more files with identical content, passed to the
+/// callback of [`find_duplicates_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Hash of the content shared by all files.
+    pub hash: Hash,
+    /// Size of each file in bytes. Files matching as normalized text may
+    /// differ in size; the group then has the size of its smallest file.
+    pub size: u64,
+    /// Paths of the files, in lexicographic order.
+    pub paths: Vec<std::path::PathBuf>,
+}
+
+/// Like [`find_duplicates_in`], but passes each group to `on_group` as soon
+/// as it is complete instead of collecting all of them, and returns the
+/// statistics about the scan.
+///
+/// A group is complete once the walk is done and every file of its size is
+/// hashed, so the first groups are reported while larger files are still
+/// being read. Groups arrive in no particular order; those completed at the
+/// same time are sorted like in [`find_duplicates`].
+pub fn find_duplicates_with<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+    mut on_group: impl FnMut(DuplicateGroup),
+) -> Result<ScanStats, std::io::Error> {
+    let exclude = build_exclude_set(options)?;
+    let roots: Vec<&std::path::Path> = paths.iter().map(AsRef::as_ref).collect();
+    let mut group_stats = ScanStats::default();
+    let mut error = None;
+    let mut completed = |file_groups: Vec<(Hash, FileInfo)>| {
+        let mut duplicates = Vec::new();
+        for (k, v) in file_groups {
+            if error.is_some() {
+                return;
+            }
+            match finish_group(&k, v, options, &mut group_stats) {
+                Ok(groups) => duplicates.extend(groups.map(|v| (k, v))),
+                Err(e) => error = Some(e),
+            }
+        }
+        sort_groups(&mut duplicates);
+        for (hash, v) in duplicates {
+            on_group(DuplicateGroup {
+                hash,
+                size: v.size,
+                paths: v.paths,
+            });
+        }
+    };
+    let (_, mut stats) = scan_with(
+        |state| walk_roots(&roots, options, state),
+        exclude,
+        options,
+        Some(&mut completed),
+    )?;
+    if let Some(e) = error {
+        return Err(e);
+    }
+    stats.errors.extend(group_stats.errors);
+    stats.vanished += group_stats.vanished;
+    stats.normalized.extend(group_stats.normalized);
+    stats.likely.extend(group_stats.likely);
+    Ok(stats)
+}
+
+/// Sorts the paths within each group, and the groups by descending size,
+/// then by hash.
+pub(crate) fn sort_groups(duplicates: &mut [(Hash, FileInfo)]) {
+    for (_, v) in duplicates.iter_mut() {
+        v.paths.sort();
+    }
+    duplicates.sort_by(|(hash_a, a), (hash_b, b)| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| hash_a.cmp(hash_b))
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+}
+
+/// Like [`find_duplicates_in`], but only reports files below `paths` that
+/// have a copy below one of the `reference` trees.
+///
+/// Every returned group contains at least one file from a reference tree
+/// and at least one file from `paths`. Duplicates found only within `paths`
+/// or only within the reference are left out. Files are told apart by path,
+/// so none of `paths` may be inside a reference tree.
+pub fn find_duplicates_of_reference<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+    reference: &[P],
+    paths: &[Q],
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let roots: Vec<&std::path::Path> = reference
+        .iter()
+        .map(AsRef::as_ref)
+        .chain(paths.iter().map(AsRef::as_ref))
+        .collect();
+    let (mut duplicates, stats) = find_duplicates_in(&roots, options)?;
+    let is_reference = |path: &std::path::Path| reference.iter().any(|r| path.starts_with(r));
+    duplicates.retain(|(_, v)| {
+        v.paths.iter().any(|p| is_reference(p)) && v.paths.iter().any(|p| !is_reference(p))
+    });
+    Ok((duplicates, stats))
+}
+
+/// Like [`find_duplicates_of_reference`], but takes the reference files from
+/// `manifest`, e.g. a listing by [`hash_all_files`] of data that is no longer
+/// at hand, instead of reading them.
+///
+/// Every returned group contains the manifest entries with its hash and at
+/// least one file from `paths`. Only files of a size listed in the manifest
+/// are hashed, and files whose path is listed are taken as the entry itself
+/// rather than a copy. The manifest must be hashed with `options.hash`.
+pub fn find_duplicates_of_manifest<P: AsRef<std::path::Path>>(
+    manifest: &[HashedFile],
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let mut reference: HashMap<Hash, FileInfo> = HashMap::new();
+    for f in manifest {
+        match reference.entry(f.hash) {
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(FileInfo::new(f.path.clone(), f.size));
+            }
+            std::collections::hash_map::Entry::Occupied(mut e) => {
+                e.get_mut().add_path(f.path.clone())
+            }
+        }
+    }
+    let sizes: HashSet<u64> = manifest.iter().map(|f| f.size).collect();
+    let listed: HashSet<&std::path::Path> = manifest.iter().map(|f| f.path.as_path()).collect();
+    let exclude = build_exclude_set(options)?;
+    let roots: Vec<&std::path::Path> = paths.iter().map(AsRef::as_ref).collect();
+    let progress = Progress::new(options.progress);
+    let cancelled = std::sync::atomic::AtomicBool::new(false);
+    let mut files = Vec::new();
+    let mut found = |path: std::path::PathBuf, size| {
+        if sizes.contains(&size) && !listed.contains(path.as_path()) {
+            files.push(path);
+        }
+    };
+    let mut state = ScanState::new(exclude, options, &progress, &cancelled, &mut found);
+    let result = walk_roots(&roots, options, &mut state);
+    let mut stats = state.stats;
+    progress.finish();
+    result?;
+    stats.files_scanned = progress.total_files_scanned();
+    let options = ScanOptions {
+        hash_all: true,
+        ..options.clone()
+    };
+    let (file_map, hash_stats) = scan_files(&files, &options)?;
+    stats.errors.extend(hash_stats.errors);
+    stats.vanished += hash_stats.vanished;
+    stats.bytes_hashed += hash_stats.bytes_hashed;
+    let mut duplicates: Vec<(Hash, FileInfo)> = file_map
+        .into_iter()
+        .filter_map(|(hash, mut v)| {
+            let copies = reference.get(&hash).filter(|r| r.size == v.size)?;
+            v.paths.extend(copies.paths.iter().cloned());
+            Some((hash, v))
+        })
+        .collect();
+    sort_groups(&mut duplicates);
+    Ok((duplicates, stats))
+}
+
+/// Walks the directory trees below `paths` and returns all groups of two or
+/// more files with the same name and size, without reading any content.
+///
+/// The files in a group are only likely duplicates; pass their paths to
+/// [`find_duplicates_among`] to confirm them. Groups are ordered by
+/// descending size, then by name, and the paths within each group are
+/// sorted.
+pub fn find_name_size_candidates<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(Vec<FileInfo>, ScanStats), std::io::Error> {
+    let exclude = build_exclude_set(options)?;
+    let roots: Vec<&std::path::Path> = paths.iter().map(AsRef::as_ref).collect();
+    let progress = Progress::new(options.progress);
+    let cancelled = std::sync::atomic::AtomicBool::new(false);
+    let mut candidates: HashMap<(std::ffi::OsString, u64), FileInfo> = HashMap::new();
+    let mut found = |path: std::path::PathBuf, size| {
+        let Some(name) = path.file_name().map(|name| name.to_owned()) else {
+            return;
+        };
+        match candidates.entry((name, size)) {
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(FileInfo::new(path, size));
+            }
+            std::collections::hash_map::Entry::Occupied(mut e) => e.get_mut().add_path(path),
+        }
+    };
+    let mut state = ScanState::new(exclude, options, &progress, &cancelled, &mut found);
+    let result = walk_roots(&roots, options, &mut state);
+    let mut stats = state.stats;
+    progress.finish();
+    result?;
+    stats.files_scanned = progress.total_files_scanned();
+    let mut groups: Vec<((std::ffi::OsString, u64), FileInfo)> = candidates
+        .into_iter()
+        .filter(|(_, v)| v.paths.len() >= options.min_copies())
+        .collect();
+    for (_, v) in groups.iter_mut() {
+        v.paths.sort();
+    }
+    groups.sort_by(|((name_a, _), a), ((name_b, _), b)| {
+        b.size.cmp(&a.size).then_with(|| name_a.cmp(name_b))
+    });
+    Ok((groups.into_iter().map(|(_, v)| v).collect(), stats))
+}
+
+/// A file listed by [`hash_all_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashedFile {
+    pub path: std::path::PathBuf,
+    pub size: u64,
+    pub hash: Hash,
+}
+
+/// Walks the directory trees below `paths` and hashes every file considered
+/// by `options`, whether it has a duplicate or not. Each hardlink is listed
+/// with its own path. The files are sorted by path.
+pub fn hash_all_files<P: AsRef<std::path::Path>>(
+    paths: &[P],
+    options: &ScanOptions,
+) -> Result<(Vec<HashedFile>, ScanStats), std::io::Error> {
+    let options = ScanOptions {
+        hash_all: true,
+        ignore_hardlinks: false,
+        ..options.clone()
+    };
+    let (file_map, stats) = scan_on_directories(paths, &options)?;
+    let mut files: Vec<HashedFile> = file_map
+        .into_iter()
+        .flat_map(|(hash, v)| {
+            v.paths.into_iter().map(move |path| HashedFile {
+                path,
+                size: v.size,
+                hash,
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((files, stats))
+}
+
+/// Data hashed by [`benchmark_hashing`].
+#[derive(Debug, Clone, Copy)]
+pub enum BenchmarkSource<'a> {
+    /// Read and hash a file, measuring disk and hashing throughput together.
+    File(&'a std::path::Path),
+    /// Hash an in-memory buffer, measuring the hash algorithm alone.
+    Buffer(&'a [u8]),
+}
+
+/// Outcome of [`benchmark_hashing`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    /// Total number of bytes hashed by all threads.
+    pub bytes: u64,
+    pub elapsed: std::time::Duration,
+}
+
+impl BenchmarkResult {
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Hashes `source` over and over on `jobs` threads until at least `duration`
+/// has passed, to measure hashing throughput. Data is hashed in pieces of
+/// `chunk_size` bytes, which is clamped like `ScanOptions::chunk_size`.
+/// With `mmap`, files are hashed like with `ScanOptions::mmap`.
+pub fn benchmark_hashing(
+    source: BenchmarkSource,
+    algorithm: HashAlgorithm,
+    jobs: usize,
+    chunk_size: usize,
+    mmap: bool,
+    duration: std::time::Duration,
+) -> Result<BenchmarkResult, std::io::Error> {
+    let size = match source {
+        BenchmarkSource::File(path) => std::fs::metadata(path)?.len(),
+        BenchmarkSource::Buffer(data) => data.len() as u64,
+    };
+    let progress = Progress::default();
+    let chunk_size = chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    let start = std::time::Instant::now();
+    let bytes = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut bytes = 0;
+                    while start.elapsed() < duration {
+                        match source {
+                            BenchmarkSource::File(path) => {
+                                hash_file(path, &algorithm, size, chunk_size, mmap, &progress)?;
+                            }
+                            BenchmarkSource::Buffer(data) => {
+                                let mut hasher = algorithm.hasher();
+                                for chunk in data.chunks(chunk_size) {
+                                    hasher.update(chunk);
+                                }
+                                hasher.finalize();
+                            }
+                        }
+                        bytes += size;
+                    }
+                    Ok::<u64, std::io::Error>(bytes)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("benchmark thread panicked"))
+            .sum::<Result<u64, _>>()
+    })?;
+    Ok(BenchmarkResult {
+        bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Formats a byte count using binary units, e.g. `1.4 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["bytes", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
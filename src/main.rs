@@ -1,153 +1,3580 @@
-use clap::{self, Parser};
+use clap::{self, CommandFactory, Parser};
 use colored::Colorize;
-use sha_256;
-use std::{collections::HashMap, io::Write};
+use duplicate_checker::{
+    benchmark_hashing, files_equal, find_duplicate_dirs, find_duplicates_among, find_duplicates_in,
+    find_duplicates_of_manifest, find_duplicates_of_reference, find_duplicates_with,
+    find_name_size_candidates, format_bytes, hash_all_files, BenchmarkSource, FileInfo, Hash,
+    HashAlgorithm, HashedFile, ScanOptions, ScanStats, HASH_CHUNK_SIZE, MAX_CHUNK_SIZE,
+    MIN_CHUNK_SIZE,
+};
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-fn scan_on_directory(path: &std::path::Path) -> Result<HashMap<Hash, FileInfo>, std::io::Error> {
-    let mut file_map = HashMap::new();
-    scan_rec(path, &mut file_map)?;
-    Ok(file_map)
+/// Format of the duplicate report.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable listing
+    #[default]
+    Text,
+    /// JSON array of duplicate groups
+    Json,
+    /// One JSON object per duplicate group and line
+    Jsonl,
+    /// CSV with one `group,hash,size,path` row per file
+    Csv,
+    /// Redundant paths separated by NUL bytes, selected by `--print0`
+    #[value(skip)]
+    Print0,
 }
 
-fn scan_rec(
+/// Short identifiers of the groups: the first 8 hex digits of the hash, with
+/// `-2`, `-3`, ... appended for groups sharing a hash after verifying.
+fn group_ids(duplicates: &[(Hash, FileInfo)]) -> Vec<String> {
+    let mut seen = std::collections::HashMap::new();
+    duplicates
+        .iter()
+        .map(|(k, _)| group_id(k, &mut seen))
+        .collect()
+}
+
+/// Identifier of the next group with hash `k`, counting the groups per hash
+/// in `seen`.
+fn group_id(k: &Hash, seen: &mut std::collections::HashMap<Hash, usize>) -> String {
+    let count = seen.entry(*k).or_default();
+    *count += 1;
+    let prefix: String = k.to_string().chars().take(8).collect();
+    match *count {
+        1 => prefix,
+        n => format!("{prefix}-{n}"),
+    }
+}
+
+fn group_json(id: &str, k: &Hash, v: &FileInfo, report: &ReportOptions) -> serde_json::Value {
+    let mut group = serde_json::json!({
+        "id": id,
+        "hash": k.to_string(),
+        "size": v.size,
+        "paths": v.paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+    });
+    if report.normalized.contains(k) {
+        group["normalized"] = true.into();
+    }
+    if report.likely.contains(k) {
+        group["likely"] = true.into();
+    }
+    group
+}
+
+fn write_json(
+    duplicates: &[(Hash, FileInfo)],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let groups: Vec<serde_json::Value> = duplicates
+        .iter()
+        .zip(group_ids(duplicates))
+        .map(|((k, v), id)| group_json(&id, k, v, report))
+        .collect();
+    writeln!(out, "{}", serde_json::to_string_pretty(&groups)?)?;
+    Ok(())
+}
+
+/// Writes each group as a compact JSON object on its own line.
+fn write_jsonl(
+    duplicates: &[(Hash, FileInfo)],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    for ((k, v), id) in duplicates.iter().zip(group_ids(duplicates)) {
+        writeln!(out, "{}", group_json(&id, k, v, report))?;
+    }
+    Ok(())
+}
+
+/// When to use colors in the output.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    /// Use colors if stdout is a terminal and NO_COLOR is not set
+    Auto,
+    /// Always use colors
+    Always,
+    /// Never use colors
+    Never,
+}
+
+impl ColorChoice {
+    /// Enables or disables colors for output going to stdout, or to a file
+    /// if `to_file` is set.
+    fn apply(self, to_file: bool) {
+        let enabled = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && !to_file
+                    && std::io::stdout().is_terminal()
+            }
+        };
+        colored::control::set_override(enabled);
+    }
+}
+
+/// Settings for presenting the scan results.
+#[derive(Debug, Clone, Default)]
+struct ReportOptions {
+    format: OutputFormat,
+    /// Leave out the banner and summary lines.
+    quiet: bool,
+    /// Protected trees that duplicates are looked up in. Their files are
+    /// marked in the report and never modified by the fix phase.
+    reference: Vec<std::path::PathBuf>,
+    /// Files of an offline reference tree, from `--reference-manifest`. They
+    /// are treated like the files below `reference`.
+    manifest: Option<Vec<HashedFile>>,
+    manifest_paths: std::collections::HashSet<std::path::PathBuf>,
+    /// Only report this many groups, those freeing the most space.
+    top: Option<usize>,
+    /// Only report groups with a path matching this pattern.
+    filter: Option<regex::Regex>,
+    /// Show at most this many paths per group in the text report.
+    max_list: Option<usize>,
+    /// Show paths in the text report relative to this directory.
+    relative_to: Option<std::path::PathBuf>,
+    /// Report duplicate directories instead of files.
+    dirs: bool,
+    /// Report files with the same name and size without hashing them.
+    by_name_size: bool,
+    /// List every file with its hash instead of reporting duplicates.
+    list_only: bool,
+    /// Canonicalize all paths before they are reported.
+    absolute: bool,
+    /// Order the groups by file extension, with a header for each one.
+    group_by_ext: bool,
+    /// Show the distribution of copies and reclaimable space per group.
+    histogram: bool,
+    /// Write groups as soon as they are found instead of sorted after the scan.
+    stream: bool,
+    /// Show the modification time, size and directory of each path in the
+    /// text report.
+    details: bool,
+    /// Groups whose files only match after normalizing their text.
+    normalized: std::collections::HashSet<Hash>,
+    /// Byte-identical subsets of the normalized groups, which are the only
+    /// files fixed without `--fix-normalized`.
+    byte_identical: std::collections::HashMap<Hash, Vec<FileInfo>>,
+    /// Groups whose files were only hashed partially and not compared.
+    likely: std::collections::HashSet<Hash>,
+    /// Fraction of the file sizes that was scanned, to extrapolate from.
+    sample: Option<f64>,
+}
+
+impl ReportOptions {
+    fn is_reference(&self, path: &std::path::Path) -> bool {
+        self.reference.iter().any(|r| path.starts_with(r)) || self.manifest_paths.contains(path)
+    }
+
+    /// Whether duplicates are looked up in reference files.
+    fn has_reference(&self) -> bool {
+        !self.reference.is_empty() || self.manifest.is_some()
+    }
+
+    /// Number of files in the group `k` that can be removed: all but one, or
+    /// all but the reference files, which are never touched.
+    fn redundant_files(&self, k: &Hash, v: &FileInfo) -> usize {
+        match self.byte_identical.get(k) {
+            Some(groups) => groups.iter().map(|g| self.redundant_in(g)).sum(),
+            None => self.redundant_in(v),
+        }
+    }
+
+    /// Bytes freed by removing the redundant files of the group `k`.
+    fn reclaimable_bytes(&self, k: &Hash, v: &FileInfo) -> u64 {
+        match self.byte_identical.get(k) {
+            Some(groups) => groups
+                .iter()
+                .map(|g| self.redundant_in(g) as u64 * g.size)
+                .sum(),
+            None => self.redundant_in(v) as u64 * v.size,
+        }
+    }
+
+    fn redundant_in(&self, v: &FileInfo) -> usize {
+        if self.has_reference() {
+            v.paths.iter().filter(|p| !self.is_reference(p)).count()
+        } else {
+            v.paths.len() - 1
+        }
+    }
+
+    /// Whether `path` matches the `--filter` pattern, if there is one.
+    fn matches_filter(&self, path: &std::path::Path) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.is_match(&path.to_string_lossy()))
+    }
+
+    /// Returns `path` as it is shown in the text report.
+    fn display<'a>(&self, path: &'a std::path::Path) -> &'a std::path::Path {
+        self.relative_to
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .unwrap_or(path)
+    }
+}
+
+/// Strategy for automatically choosing the file to keep in a duplicate group.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum KeepStrategy {
+    /// File with the oldest modification time
+    Oldest,
+    /// File with the newest modification time
+    Newest,
+    /// File with the shortest path
+    ShortestPath,
+    /// File with the longest path
+    LongestPath,
+    /// File with the fewest directories in its path
+    Shallowest,
+    /// File with the most directories in its path
+    Deepest,
+    /// First file listed in the group
+    First,
+}
+
+/// What to put in place of a redundant copy instead of just deleting it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ReplaceMode {
+    /// Replace the copy by a hardlink to the kept file
+    Hardlink,
+    /// Replace the copy by a copy-on-write clone of the kept file, which
+    /// shares its data blocks but keeps separate metadata
+    Reflink,
+}
+
+/// When to ask for confirmation before changing files selected interactively.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ConfirmMode {
+    /// Once, after files were selected in all groups
+    #[default]
+    Batch,
+    /// After each group
+    Group,
+    /// Never ask
+    Never,
+}
+
+/// Settings for the fix phase that removes duplicates.
+#[derive(Debug, Clone, Default)]
+struct FixOptions {
+    /// Choose the file to keep automatically instead of prompting.
+    keep: Option<KeepStrategy>,
+    /// Only report what would be deleted.
+    dry_run: bool,
+    /// Replace redundant copies instead of deleting them.
+    replace_with: Option<ReplaceMode>,
+    /// Move redundant copies to the trash instead of deleting them.
+    trash: bool,
+    /// Rename redundant copies by appending this suffix instead of deleting
+    /// them.
+    rename_suffix: Option<String>,
+    /// Remove directories left empty by the fix phase.
+    prune_empty_dirs: bool,
+    /// When to confirm the selected changes before applying them.
+    confirm: ConfirmMode,
+    /// Whether files are selected at the prompt, so that questions can be
+    /// asked while fixing.
+    interactive: bool,
+    /// Make read-only files writable so that they can be removed.
+    force: bool,
+    /// Directories whose copy is kept, in order of priority.
+    prefer: Vec<std::path::PathBuf>,
+    /// Groups of smaller files are listed but left untouched.
+    min_size: Option<u64>,
+    /// Also fix groups whose files only match as normalized text.
+    fix_normalized: bool,
+    /// Write the removals to this shell script instead of carrying them out.
+    script: Option<std::sync::Arc<Script>>,
+    /// Record the files chosen to keep in each group.
+    record_decisions: Option<std::sync::Arc<DecisionLog>>,
+    /// Keep the recorded files instead of choosing, by group hash. Groups
+    /// without a decision are left untouched.
+    decisions: Option<std::sync::Arc<std::collections::HashMap<String, Vec<std::path::PathBuf>>>>,
+}
+
+/// The files kept in one group, stored as a line of JSON by
+/// `--record-decisions`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Decision {
+    hash: String,
+    keep: Vec<std::path::PathBuf>,
+}
+
+/// File the decisions of the fix phase are written to as they are made, so
+/// that they survive an interrupted session.
+#[derive(Debug)]
+struct DecisionLog {
+    path: std::path::PathBuf,
+    file: std::sync::Mutex<std::fs::File>,
+}
+
+impl DecisionLog {
+    fn create(path: &std::path::Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: std::sync::Mutex::new(std::fs::File::create(path)?),
+        })
+    }
+
+    /// Appends that the files at `keep` were kept in the group with hash `k`.
+    /// The paths are stored in absolute form, so that the decisions also apply
+    /// when run from another directory.
+    fn record(&self, k: &Hash, keep: Vec<std::path::PathBuf>) -> Result<(), std::io::Error> {
+        let decision = Decision {
+            hash: k.to_string(),
+            keep: keep.iter().map(|p| absolute_path(p)).collect(),
+        };
+        let mut line = serde_json::to_vec(&decision)?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .write_all(&line)
+    }
+}
+
+/// Reads the decisions written by `--record-decisions`. Later decisions for
+/// the same group replace earlier ones.
+fn read_decisions(
+    path: &std::path::Path,
+) -> Result<std::collections::HashMap<String, Vec<std::path::PathBuf>>, std::io::Error> {
+    let data = std::fs::read(path)?;
+    let mut decisions = std::collections::HashMap::new();
+    for (number, line) in data.split(|&b| b == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let decision: Decision = serde_json::from_slice(line).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: {e}", number + 1),
+            )
+        })?;
+        decisions.insert(decision.hash, decision.keep);
+    }
+    Ok(decisions)
+}
+
+/// Shell script collecting `rm` commands for review instead of removing files.
+#[derive(Debug)]
+struct Script {
+    path: std::path::PathBuf,
+    file: std::sync::Mutex<std::io::BufWriter<std::fs::File>>,
+}
+
+impl Script {
+    /// Creates the script at `path`, executable on Unix.
+    fn create(path: &std::path::Path) -> Result<Self, std::io::Error> {
+        let file = std::fs::File::create(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+        }
+        let mut file = std::io::BufWriter::new(file);
+        writeln!(file, "#!/bin/sh")?;
+        writeln!(
+            file,
+            "# Removes the redundant copies found by duplicate_checker."
+        )?;
+        writeln!(file, "# Review before running.")?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: std::sync::Mutex::new(file),
+        })
+    }
+
+    /// Writes an empty line and a comment starting a section. The comment must
+    /// not contain line breaks, which would end it.
+    fn comment(&self, comment: &str) -> Result<(), std::io::Error> {
+        writeln!(self.lock(), "\n# {comment}")
+    }
+
+    /// Writes the removal of `path`, made absolute so that the script can be
+    /// run from any directory.
+    fn remove(&self, path: &std::path::Path) -> Result<(), std::io::Error> {
+        let path = absolute_path(path);
+        let mut file = self.lock();
+        file.write_all(b"rm -f -- ")?;
+        file.write_all(&shell_quote(&path))?;
+        file.write_all(b"\n")
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, std::io::BufWriter<std::fs::File>> {
+        self.file
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn flush(&self) -> Result<(), std::io::Error> {
+        self.lock().flush()
+    }
+}
+
+/// Quotes `path` for a POSIX shell. Within single quotes every byte stands for
+/// itself, except for the single quote that ends the quoting.
+fn shell_quote(path: &std::path::Path) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in path.as_os_str().as_encoded_bytes() {
+        if b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Files removed (or, in a dry run, selected for removal) by the fix phase.
+#[derive(Debug, Default)]
+struct FixSummary {
+    files: usize,
+    bytes: u64,
+    /// Directories that files were removed from.
+    touched_dirs: std::collections::BTreeSet<std::path::PathBuf>,
+    /// Whether the fix phase was stopped early by Ctrl-C.
+    interrupted: bool,
+}
+
+/// Set by the Ctrl-C handler. The fix phase stops before changing the next file.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Events delivered to the interactive prompt.
+enum InputEvent {
+    /// A line read from stdin, empty at the end of input.
+    Line(Result<String, std::io::Error>),
+    /// The user pressed Ctrl-C.
+    Interrupted,
+}
+
+/// Stdin lines and Ctrl-C presses, merged into one channel so that an
+/// interrupt also ends a pending prompt.
+struct Input {
+    sender: std::sync::mpsc::Sender<InputEvent>,
+    receiver: std::sync::Mutex<std::sync::mpsc::Receiver<InputEvent>>,
+    reader_started: std::sync::Once,
+}
+
+static INPUT: std::sync::OnceLock<Input> = std::sync::OnceLock::new();
+
+/// Installs a Ctrl-C handler that lets the fix phase stop between two files
+/// instead of killing the process.
+fn install_interrupt_handler() -> Result<(), ctrlc::Error> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handler_sender = sender.clone();
+    ctrlc::set_handler(move || {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let _ = handler_sender.send(InputEvent::Interrupted);
+    })?;
+    let _ = INPUT.set(Input {
+        sender,
+        receiver: std::sync::Mutex::new(receiver),
+        reader_started: std::sync::Once::new(),
+    });
+    Ok(())
+}
+
+fn interrupted_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Interrupted, "interrupted by user")
+}
+
+/// Reads a line from stdin, failing with `ErrorKind::Interrupted` if Ctrl-C
+/// is pressed while waiting. Returns an empty string at the end of input.
+fn read_input_line() -> Result<String, std::io::Error> {
+    let Some(input) = INPUT.get() else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return Ok(line);
+    };
+    input.reader_started.call_once(|| {
+        let sender = input.sender.clone();
+        std::thread::spawn(move || loop {
+            let mut line = String::new();
+            let result = std::io::stdin().read_line(&mut line).map(|_| line);
+            let done = result.as_ref().map_or(true, |line| line.is_empty());
+            if sender.send(InputEvent::Line(result)).is_err() || done {
+                break;
+            }
+        });
+    });
+    let receiver = input.receiver.lock().unwrap_or_else(|e| e.into_inner());
+    match receiver.recv() {
+        Ok(InputEvent::Line(result)) => result,
+        Ok(InputEvent::Interrupted) => Err(interrupted_error()),
+        Err(_) => Ok(String::new()),
+    }
+}
+
+/// Splits `data` into records of fields, undoing the quoting of [`csv_field`].
+fn csv_records(data: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Reads the listing of an earlier `--list-only` run, written as JSON, JSON
+/// Lines or CSV, with hashes computed by `algorithm`.
+fn read_manifest(
     path: &std::path::Path,
-    filemap: &mut HashMap<Hash, FileInfo>,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<HashedFile>, std::io::Error> {
+    #[derive(serde::Deserialize)]
+    struct Entry {
+        hash: String,
+        size: u64,
+        path: std::path::PathBuf,
+    }
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let data = std::fs::read_to_string(path)?;
+    let entries: Vec<Entry> = match data.trim_start().as_bytes().first() {
+        Some(b'[') => serde_json::from_str(&data)?,
+        Some(b'{') => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+        _ => {
+            let mut records = csv_records(&data).map_err(invalid)?.into_iter();
+            if records
+                .next()
+                .is_some_and(|header| header != ["hash", "size", "path"])
+            {
+                return Err(invalid(
+                    "expected JSON or CSV with a hash,size,path header".to_string(),
+                ));
+            }
+            records
+                .map(|record| match <[String; 3]>::try_from(record) {
+                    Ok([hash, size, path]) => Ok(Entry {
+                        size: size
+                            .parse()
+                            .map_err(|e| invalid(format!("invalid size {size:?}: {e}")))?,
+                        hash,
+                        path: path.into(),
+                    }),
+                    Err(record) => Err(invalid(format!(
+                        "expected 3 fields, found {}",
+                        record.len()
+                    ))),
+                })
+                .collect::<Result<_, _>>()?
+        }
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let hash = Hash::from_hex(algorithm, &entry.hash).ok_or_else(|| {
+                invalid(format!(
+                    "invalid hash {:?} for {:?}",
+                    entry.hash, entry.path
+                ))
+            })?;
+            // digests of another algorithm would silently never match
+            if hash.as_bytes().len() != algorithm.digest_len() {
+                let name = clap::ValueEnum::to_possible_value(&algorithm)
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default();
+                return Err(invalid(format!(
+                    "hash {:?} for {:?} is not a {} digest, list the files with the same --hash",
+                    entry.hash, entry.path, name
+                )));
+            }
+            Ok(HashedFile {
+                path: entry.path,
+                size: entry.size,
+                hash,
+            })
+        })
+        .collect()
+}
+
+/// Quotes a CSV field if it contains characters with special meaning.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+fn write_csv(duplicates: &[(Hash, FileInfo)], out: &mut dyn Write) -> Result<(), std::io::Error> {
+    writeln!(out, "group,hash,size,path")?;
+    for ((k, v), id) in duplicates.iter().zip(group_ids(duplicates)) {
+        for path in &v.paths {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                id,
+                k,
+                v.size,
+                csv_field(&path.to_string_lossy())
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the groups of files sharing name and size, which were not compared
+/// by content.
+fn write_candidates(
+    candidates: &[FileInfo],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let name = |v: &FileInfo| {
+        v.paths[0]
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    match report.format {
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let groups: Vec<serde_json::Value> = candidates
+                .iter()
+                .map(|v| {
+                    serde_json::json!({
+                        "name": name(v),
+                        "size": v.size,
+                        "paths": v.paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            if report.format == OutputFormat::Json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&groups)?)?;
+            } else {
+                for group in groups {
+                    writeln!(out, "{group}")?;
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "name,size,path")?;
+            for v in candidates {
+                for path in &v.paths {
+                    writeln!(
+                        out,
+                        "{},{},{}",
+                        csv_field(&name(v)),
+                        v.size,
+                        csv_field(&path.to_string_lossy())
+                    )?;
+                }
+            }
+        }
+        OutputFormat::Print0 | OutputFormat::Text => {
+            for v in candidates {
+                writeln!(
+                    out,
+                    "Candidate set {:?} (filesize: {} bytes, unverified):",
+                    name(v),
+                    v.size
+                )?;
+                let shown = report.max_list.unwrap_or(v.paths.len()).min(v.paths.len());
+                for (idx, f) in v.paths.iter().enumerate().take(shown) {
+                    writeln!(out, " {} - {:?}", idx + 1, report.display(f))?;
+                }
+                if shown < v.paths.len() {
+                    writeln!(out, " ... and {} more", v.paths.len() - shown)?;
+                }
+            }
+            if !report.quiet {
+                let redundant_files: usize = candidates.iter().map(|v| v.paths.len() - 1).sum();
+                let reclaimable_bytes: u64 = candidates
+                    .iter()
+                    .map(|v| (v.paths.len() as u64 - 1) * v.size)
+                    .sum();
+                writeln!(
+                    out,
+                    "Found {} candidate groups by name and size, {} redundant files, {} reclaimable (unverified)",
+                    candidates.len(),
+                    redundant_files,
+                    format_bytes(reclaimable_bytes)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the hash, size and path of every file, separated by two spaces in
+/// the text report.
+fn write_listing(
+    files: &[HashedFile],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let file_json = |f: &HashedFile| {
+        serde_json::json!({
+            "hash": f.hash.to_string(),
+            "size": f.size,
+            "path": f.path.to_string_lossy(),
+        })
+    };
+    match report.format {
+        OutputFormat::Json => {
+            let files: Vec<serde_json::Value> = files.iter().map(file_json).collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&files)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for f in files {
+                writeln!(out, "{}", file_json(f))?;
+            }
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "hash,size,path")?;
+            for f in files {
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    f.hash,
+                    f.size,
+                    csv_field(&f.path.to_string_lossy())
+                )?;
+            }
+        }
+        OutputFormat::Print0 | OutputFormat::Text => {
+            for f in files {
+                writeln!(
+                    out,
+                    "{}  {}  {}",
+                    f.hash,
+                    f.size,
+                    report.display(&f.path).display()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes all but the first path of each group, each terminated by a NUL byte.
+/// With reference trees, all paths outside of them are written instead.
+fn write_print0(
+    duplicates: &[(Hash, FileInfo)],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    for (_, v) in duplicates {
+        let skip = if report.has_reference() { 0 } else { 1 };
+        for path in v.paths.iter().skip(skip) {
+            if report.is_reference(path) {
+                continue;
+            }
+            out.write_all(path.as_os_str().as_encoded_bytes())?;
+            out.write_all(b"\0")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the duplicate report to `out` and runs the fix phase if requested.
+fn handle_duplicates(
+    duplicates: &mut [(Hash, FileInfo)],
+    report: &ReportOptions,
+    fix: Option<&FixOptions>,
+    out: &mut dyn Write,
+) -> Result<FixSummary, std::io::Error> {
+    // the displayed indices and the keep strategies rely on a stable order
+    for (_, v) in duplicates.iter_mut() {
+        v.paths.sort();
+    }
+    let mut fixed = FixSummary::default();
+    match report.format {
+        OutputFormat::Json => return write_json(duplicates, report, out).map(|()| fixed),
+        OutputFormat::Jsonl => return write_jsonl(duplicates, report, out).map(|()| fixed),
+        OutputFormat::Csv => return write_csv(duplicates, out).map(|()| fixed),
+        OutputFormat::Print0 => return write_print0(duplicates, report, out).map(|()| fixed),
+        OutputFormat::Text => {}
+    }
+    if fix.is_some() {
+        if let Err(e) = install_interrupt_handler() {
+            eprintln!("Warning: unable to handle Ctrl-C: {e}");
+        }
+    }
+    let mut pending = Vec::new();
+    let mut section = None;
+    let ids = group_ids(duplicates);
+    'groups: for ((k, v), id) in duplicates.iter().zip(&ids) {
+        if report.group_by_ext && section.as_ref() != Some(&extension(v)) {
+            let ext = extension(v);
+            let groups = duplicates
+                .iter()
+                .filter(|(_, v)| extension(v) == ext)
+                .count();
+            match &ext {
+                Some(ext) => writeln!(out, "== .{ext} files ({groups} groups) ==")?,
+                None => writeln!(out, "== Files without extension ({groups} groups) ==")?,
+            }
+            section = Some(ext);
+        }
+        write_group(id, k, v, report, fix.is_some(), out)?;
+        if let Some(min_size) = fix.and_then(|fix| fix.min_size).filter(|&min| v.size < min) {
+            writeln!(
+                out,
+                " Not eligible for fixing, smaller than {}",
+                format_bytes(min_size)
+            )?;
+        } else if let (Some(fix), Some(groups)) = (fix, report.byte_identical.get(k)) {
+            let groups: Vec<&FileInfo> = groups.iter().filter(|g| g.paths.len() > 1).collect();
+            if groups.is_empty() {
+                writeln!(
+                    out,
+                    " Not fixed, the files only match as normalized text (use --fix-normalized)"
+                )?;
+                continue;
+            }
+            writeln!(
+                out,
+                " Only fixing byte-identical copies, the others only match as normalized text (use --fix-normalized)"
+            )?;
+            for g in groups {
+                writeln!(out, " Byte-identical copies:")?;
+                for (idx, f) in g.paths.iter().enumerate() {
+                    writeln!(out, " {} - {:?}", idx + 1, report.display(f))?;
+                }
+                match fix_group(k, g, report, fix, &mut pending, &mut fixed, out) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        writeln!(out, "Stopped fixing, remaining groups were left untouched.")?;
+                        break 'groups;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                        fixed.interrupted = true;
+                        break 'groups;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        } else if let Some(fix) = fix {
+            if report.normalized.contains(k) {
+                writeln!(out, " Note: the copies are not byte-identical")?;
+            }
+            match fix_group(k, v, report, fix, &mut pending, &mut fixed, out) {
+                Ok(true) => {}
+                Ok(false) => {
+                    writeln!(out, "Stopped fixing, remaining groups were left untouched.")?;
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    fixed.interrupted = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    if let (Some(fix), false) = (fix, fixed.interrupted) {
+        match fix_batch(pending, report, fix, &mut fixed, out) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => fixed.interrupted = true,
+            result => result?,
+        }
+    }
+    if fixed.interrupted {
+        writeln!(out, "\nInterrupted, remaining groups were left untouched.")?;
+    }
+    if !report.quiet {
+        write_summary(duplicates, report, fix, &fixed, out)?;
+    }
+    Ok(fixed)
+}
+
+/// Describes the file at `path` for `--details`, e.g.
+/// `[modified 2024-05-01 12:00:00 UTC, 3 days ago, 1.2 MiB, in "photos"]`.
+fn path_details(path: &std::path::Path, report: &ReportOptions) -> String {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return format!("[unavailable: {e}]"),
+    };
+    let mut details = Vec::new();
+    if let Ok(modified) = metadata.modified() {
+        details.push(format!("modified {}", format_timestamp(modified)));
+        if let Ok(age) = modified.elapsed() {
+            details.push(format_age(age));
+        }
+    }
+    details.push(format_bytes(metadata.len()));
+    if let Some(parent) = path.parent() {
+        details.push(format!("in {:?}", report.display(parent)));
+    }
+    format!("[{}]", details.join(", "))
+}
+
+/// Formats `time` as `YYYY-MM-DD HH:MM:SS UTC`.
+fn format_timestamp(time: std::time::SystemTime) -> String {
+    let secs = match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Formats how long ago something happened, e.g. `3 days ago`.
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        86400..2_592_000 => (secs / 86400, "day"),
+        2_592_000..31_536_000 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{plural} ago")
+}
+
+/// Writes the header and the paths of one group in the text report. When
+/// fixing, all paths are shown as the selection prompt refers to them.
+fn write_group(
+    id: &str,
+    k: &Hash,
+    v: &FileInfo,
+    report: &ReportOptions,
+    fixing: bool,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    if report.dirs {
+        writeln!(
+            out,
+            "[{}] Directory set {} (total size: {} bytes):",
+            id, k, v.size
+        )?;
+    } else if v.size == 0 {
+        writeln!(out, "[{}] Hash set {} (empty files):", id, k)?;
+    } else {
+        let label = if report.normalized.contains(k) {
+            ", normalized text match"
+        } else if report.likely.contains(k) {
+            ", likely duplicates, only partially hashed"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "[{}] Hash set {} (filesize: {} bytes{}):",
+            id, k, v.size, label
+        )?;
+    }
+    let shown = match report.max_list {
+        Some(max_list) if !fixing => max_list.min(v.paths.len()),
+        _ => v.paths.len(),
+    };
+    for (idx, f) in v.paths.iter().enumerate().take(shown) {
+        write!(out, " {} - {:?}", idx + 1, report.display(f))?;
+        if report.is_reference(f) {
+            write!(out, " (reference)")?;
+        }
+        if report.details {
+            write!(out, " {}", path_details(f, report))?;
+        }
+        writeln!(out)?;
+    }
+    if shown < v.paths.len() {
+        writeln!(out, " ... and {} more", v.paths.len() - shown)?;
+    }
+    Ok(())
+}
+
+/// Writes the totals of the text report and what the fix phase did.
+fn write_summary(
+    duplicates: &[(Hash, FileInfo)],
+    report: &ReportOptions,
+    fix: Option<&FixOptions>,
+    fixed: &FixSummary,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let redundant_files: usize = duplicates
+        .iter()
+        .map(|(k, v)| report.redundant_files(k, v))
+        .sum();
+    let reclaimable_bytes: u64 = duplicates
+        .iter()
+        .map(|(k, v)| report.reclaimable_bytes(k, v))
+        .sum();
+    writeln!(
+        out,
+        "Found {} duplicate groups, {} redundant files, {} reclaimable",
+        duplicates.len(),
+        redundant_files,
+        format_bytes(reclaimable_bytes)
+    )?;
+    if !report.likely.is_empty() {
+        writeln!(
+            out,
+            "{} of the groups are likely duplicates that were only partially hashed, use --verify to confirm them",
+            report.likely.len()
+        )?;
+    }
+    if let Some(sample) = report.sample {
+        writeln!(
+            out,
+            "Estimated for all files (approximation from a {:.1}% sample): about {} redundant files, {} reclaimable",
+            sample * 100.0,
+            (redundant_files as f64 / sample).round() as u64,
+            format_bytes((reclaimable_bytes as f64 / sample) as u64)
+        )?;
+    }
+    if let Some(script) = fix.and_then(|fix| fix.script.as_ref()) {
+        writeln!(
+            out,
+            "Wrote the removal of {} files to {:?}, freeing {}",
+            fixed.files,
+            script.path,
+            format_bytes(fixed.bytes)
+        )?;
+        return Ok(());
+    }
+    if let Some(fix) = fix.filter(|fix| fix.rename_suffix.is_some()) {
+        let files = fixed.files;
+        if fix.dry_run {
+            writeln!(out, "Would rename {files} files")?;
+        } else {
+            writeln!(out, "Renamed {files} files")?;
+        }
+    } else if let Some(fix) = fix {
+        let files = fixed.files;
+        let action = match (fix.replace_with, fix.trash, fix.dry_run) {
+            (None, false, false) => format!("Deleted {files} files"),
+            (None, false, true) => format!("Would delete {files} files"),
+            (None, true, false) => format!("Moved {files} files to trash"),
+            (None, true, true) => format!("Would move {files} files to trash"),
+            (Some(ReplaceMode::Hardlink), _, false) => {
+                format!("Replaced {files} files with hardlinks")
+            }
+            (Some(ReplaceMode::Hardlink), _, true) => {
+                format!("Would replace {files} files with hardlinks")
+            }
+            (Some(ReplaceMode::Reflink), _, false) => {
+                format!("Replaced {files} files with reflinks")
+            }
+            (Some(ReplaceMode::Reflink), _, true) => {
+                format!("Would replace {files} files with reflinks")
+            }
+        };
+        writeln!(out, "{}, freeing {}", action, format_bytes(fixed.bytes))?;
+    }
+    Ok(())
+}
+
+/// Whether groups are written as soon as they are found, in the order they
+/// are completed. This is only the case with `--stream` for the text and
+/// JSON Lines reports without fixing or options that need to see all groups
+/// first.
+fn streams_groups(
+    targets: &Targets,
+    options: &ScanOptions,
+    report: &ReportOptions,
+    fix: Option<&FixOptions>,
+) -> bool {
+    report.stream
+        && matches!(targets, Targets::Dirs(_))
+        && !options.normalize_text
+        && (options.partial_hash.is_none() || options.verify)
+        && matches!(report.format, OutputFormat::Text | OutputFormat::Jsonl)
+        && fix.is_none()
+        && !report.has_reference()
+        && report.top.is_none()
+        && !report.group_by_ext
+        && !report.dirs
+        && !report.by_name_size
+        && !report.list_only
+}
+
+/// Scans `dirs` and writes each duplicate group to `out` as soon as it is
+/// complete. Returns the groups in the order they were written.
+fn stream_duplicates(
+    dirs: &[std::path::PathBuf],
+    options: &ScanOptions,
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(Vec<(Hash, FileInfo)>, ScanStats), std::io::Error> {
+    let mut duplicates = Vec::new();
+    let mut seen = std::collections::HashMap::new();
+    let mut result = Ok(());
+    let stats = find_duplicates_with(dirs, options, |group| {
+        let mut groups = vec![FileInfo {
+            paths: group.paths,
+            size: group.size,
+        }];
+        if report.absolute {
+            absolute_groups(&mut groups, |v| v);
+        }
+        let Some(mut v) = groups.pop() else {
+            return;
+        };
+        if !v.paths.iter().any(|p| report.matches_filter(p)) {
+            return;
+        }
+        v.paths.sort();
+        let id = group_id(&group.hash, &mut seen);
+        if result.is_ok() {
+            result = match report.format {
+                OutputFormat::Jsonl => {
+                    writeln!(out, "{}", group_json(&id, &group.hash, &v, report))
+                }
+                _ => write_group(&id, &group.hash, &v, report, false, out),
+            }
+            .and_then(|()| out.flush());
+        }
+        duplicates.push((group.hash, v));
+    })?;
+    result?;
+    Ok((duplicates, stats))
+}
+
+/// Splits a group of files matching as normalized text into groups of
+/// byte-identical files, in the order of their paths. Files that cannot be
+/// read form groups of their own.
+fn byte_identical_groups(v: &FileInfo) -> Vec<FileInfo> {
+    let mut paths = v.paths.clone();
+    paths.sort();
+    let mut groups: Vec<FileInfo> = Vec::new();
+    'paths: for path in paths {
+        let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+        for g in groups.iter_mut() {
+            if g.size == size && files_equal(&g.paths[0], &path, HASH_CHUNK_SIZE).unwrap_or(false) {
+                g.paths.push(path);
+                continue 'paths;
+            }
+        }
+        groups.push(FileInfo {
+            paths: vec![path],
+            size,
+        });
+    }
+    groups
+}
+
+/// Lowercase extension of the first file of a group, which `--group-by-ext`
+/// sorts and labels the groups by.
+fn extension(v: &FileInfo) -> Option<String> {
+    v.paths
+        .first()
+        .and_then(|p| p.extension())
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+/// Picks the index of the file to keep according to `strategy`.
+///
+/// Returns `None` if no file could be chosen, e.g. because modification times
+/// are unavailable.
+fn select_index_to_keep(v: &FileInfo, strategy: KeepStrategy) -> Option<usize> {
+    let modified = |idx: &usize| {
+        std::fs::metadata(&v.paths[*idx])
+            .and_then(|m| m.modified())
+            .ok()
+    };
+    let length = |idx: &usize| v.paths[*idx].as_os_str().len();
+    let depth = |idx: &usize| v.paths[*idx].components().count();
+    // the paths are sorted, so taking the first minimum breaks ties
+    // lexicographically
+    let indices = 0..v.paths.len();
+    match strategy {
+        KeepStrategy::First => Some(0),
+        KeepStrategy::ShortestPath => indices.min_by_key(length),
+        KeepStrategy::LongestPath => indices.min_by_key(|idx| std::cmp::Reverse(length(idx))),
+        KeepStrategy::Shallowest => indices.min_by_key(depth),
+        KeepStrategy::Deepest => indices.min_by_key(|idx| std::cmp::Reverse(depth(idx))),
+        KeepStrategy::Oldest => indices
+            .filter_map(|idx| modified(&idx).map(|t| (t, idx)))
+            .min()
+            .map(|(_, idx)| idx),
+        KeepStrategy::Newest => indices
+            .filter_map(|idx| modified(&idx).map(|t| (std::cmp::Reverse(t), idx)))
+            .min()
+            .map(|(_, idx)| idx),
+    }
+}
+
+/// Files selected for removal in one duplicate group.
+struct FixPlan<'a> {
+    hash: Hash,
+    info: &'a FileInfo,
+    /// Index of the file that redundant copies are linked to.
+    kept: usize,
+    remove: Vec<usize>,
+}
+
+impl<'a> FixPlan<'a> {
+    /// Plans the removal of all files but those in `indices_to_keep`.
+    ///
+    /// Returns `None` if that would not leave a single copy.
+    fn new(hash: Hash, info: &'a FileInfo, indices_to_keep: &[usize]) -> Option<Self> {
+        let kept = *indices_to_keep
+            .iter()
+            .find(|&&idx| idx < info.paths.len())?;
+        Some(Self {
+            hash,
+            info,
+            kept,
+            remove: (0..info.paths.len())
+                .filter(|idx| !indices_to_keep.contains(idx))
+                .collect(),
+        })
+    }
+}
+
+/// Which files of a group to keep in the fix phase.
+enum Choice {
+    /// Keep the files at these indices and remove the others.
+    Keep(Vec<usize>),
+    KeepAll,
+    /// Stop fixing, leaving this and all remaining groups untouched.
+    Quit,
+}
+
+/// Selects the files to keep in `v` and queues their removal in `pending`.
+///
+/// The queued plans are applied right away unless they are confirmed as a
+/// single batch at the end. Returns `false` if the user chose to stop.
+fn fix_group<'a>(
+    k: &Hash,
+    v: &'a FileInfo,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    pending: &mut Vec<FixPlan<'a>>,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<bool, std::io::Error> {
+    match choose_files_to_keep(k, v, report, fix, out)? {
+        Choice::Keep(indices_to_keep) => match FixPlan::new(*k, v, &indices_to_keep) {
+            Some(plan) => pending.push(plan),
+            None => eprintln!("Error: no file selected to keep, skipping the group."),
+        },
+        Choice::KeepAll => {}
+        Choice::Quit => return Ok(false),
+    }
+    if fix.confirm != ConfirmMode::Batch {
+        fix_batch(std::mem::take(pending), report, fix, summary, out)?;
+    }
+    Ok(true)
+}
+
+/// Selects the files to keep in `v`, prompting unless a strategy is set or
+/// the decisions of an earlier session are applied.
+fn choose_files_to_keep(
+    k: &Hash,
+    v: &FileInfo,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    out: &mut dyn Write,
+) -> Result<Choice, std::io::Error> {
+    let reference_indices: Vec<usize> = (0..v.paths.len())
+        .filter(|&idx| report.is_reference(&v.paths[idx]))
+        .collect();
+    if let (Some(decisions), true) = (&fix.decisions, reference_indices.is_empty()) {
+        let Some(keep) = decisions.get(&k.to_string()) else {
+            writeln!(out, " No recorded decision, skipping the group.")?;
+            return Ok(Choice::KeepAll);
+        };
+        let indices: Vec<usize> = (0..v.paths.len())
+            .filter(|&idx| {
+                keep.contains(&v.paths[idx]) || keep.contains(&absolute_path(&v.paths[idx]))
+            })
+            .collect();
+        if indices.is_empty() {
+            writeln!(
+                out,
+                " None of the recorded files to keep is in the group, skipping it."
+            )?;
+            return Ok(Choice::KeepAll);
+        }
+        return Ok(Choice::Keep(indices));
+    }
+    let preferred = preferred_index(v, fix);
+    Ok(match fix.keep {
+        // every copy outside the reference is redundant
+        _ if !reference_indices.is_empty() => Choice::Keep(reference_indices),
+        _ if preferred.is_some() => Choice::Keep(preferred.into_iter().collect()),
+        Some(strategy) => match select_index_to_keep(v, strategy) {
+            Some(index) => Choice::Keep(vec![index]),
+            None => {
+                eprintln!("Unable to determine which file to keep, keeping all.");
+                Choice::KeepAll
+            }
+        },
+        None => {
+            out.flush()?;
+            prompt_indices_to_keep(v)?
+        }
+    })
+}
+
+/// Picks the file below the first of the `--prefer` directories containing
+/// any. Among several files there, the `--keep` strategy decides, or the
+/// first one is taken.
+fn preferred_index(v: &FileInfo, fix: &FixOptions) -> Option<usize> {
+    let indices = fix.prefer.iter().find_map(|dir| {
+        let indices: Vec<usize> = (0..v.paths.len())
+            .filter(|&idx| v.paths[idx].starts_with(dir))
+            .collect();
+        (!indices.is_empty()).then_some(indices)
+    })?;
+    let preferred = FileInfo {
+        paths: indices.iter().map(|&idx| v.paths[idx].clone()).collect(),
+        size: v.size,
+    };
+    let selected = fix
+        .keep
+        .and_then(|strategy| select_index_to_keep(&preferred, strategy))
+        .unwrap_or(0);
+    Some(indices[selected])
+}
+
+/// Applies `plans` after asking for confirmation if required.
+fn fix_batch(
+    plans: Vec<FixPlan>,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    if plans.iter().all(|plan| plan.remove.is_empty()) {
+        return Ok(());
+    }
+    if fix.confirm != ConfirmMode::Never && !confirm_fix(&plans, fix, out)? {
+        writeln!(out, " Cancelled, no files were changed.")?;
+        return Ok(());
+    }
+    for plan in plans {
+        fix_duplicates(plan, report, fix, summary, out)?;
+    }
+    Ok(())
+}
+
+/// Asks whether the changes in `plans` should be carried out.
+///
+/// Closing stdin counts as declining.
+fn confirm_fix(
+    plans: &[FixPlan],
+    fix: &FixOptions,
+    out: &mut dyn Write,
+) -> Result<bool, std::io::Error> {
+    let files: usize = plans.iter().map(|plan| plan.remove.len()).sum();
+    let bytes: u64 = plans
+        .iter()
+        .map(|plan| plan.remove.len() as u64 * plan.info.size)
+        .sum();
+    let action = match (fix.replace_with, fix.trash) {
+        _ if fix.rename_suffix.is_some() => "rename",
+        (Some(ReplaceMode::Hardlink | ReplaceMode::Reflink), _) => "replace",
+        (None, true) => "move to trash",
+        (None, false) => "delete",
+    };
+    write!(
+        out,
+        "You are about to {action} {files} files totaling {}. Proceed? [y/N] ",
+        format_bytes(bytes)
+    )?;
+    out.flush()?;
+    let answer = read_input_line()?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Removes or replaces the files selected in `plan`.
+fn fix_duplicates(
+    plan: FixPlan,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let v = plan.info;
+    let kept = &v.paths[plan.kept];
+    // the copies are only redundant while the kept file is still around,
+    // unless it is known from a manifest of offline data
+    if !fix.dry_run
+        && !report.manifest_paths.contains(kept)
+        && std::fs::symlink_metadata(kept).is_err()
+    {
+        eprintln!(
+            "Error: {:?} is no longer accessible, skipping its group to keep a copy.",
+            kept
+        );
+        return Ok(());
+    }
+    if let Some(script) = &fix.script {
+        // debug formatting escapes line breaks
+        script.comment(&format!("keep {:?}", absolute_path(kept)))?;
+    }
+    // check every file first, so that a group is not left half cleaned up
+    let mut remove = Vec::new();
+    for f in plan.remove.iter().map(|&idx| &v.paths[idx]) {
+        if check_removable(f, report, fix, out)? {
+            remove.push(f);
+        }
+    }
+    for f in remove {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(interrupted_error());
+        }
+        match (fix.replace_with, fix.dry_run) {
+            (None, _) if report.dirs && fix.rename_suffix.is_none() => {
+                remove_dir_copy(f, kept, report, fix, summary, out)?;
+                continue;
+            }
+            (None, _) => {
+                if !remove_file(f, report, fix, summary, out)? {
+                    continue;
+                }
+            }
+            (Some(ReplaceMode::Hardlink), true) => writeln!(
+                out,
+                " Would link {:?} to {:?}",
+                report.display(f),
+                report.display(kept)
+            )?,
+            (Some(ReplaceMode::Hardlink), false) => {
+                writeln!(out, " {} {:?}", "Linking".yellow(), report.display(f))?;
+                // the link replaces the file, its permissions are only
+                // changed on request
+                if fix.force {
+                    clear_readonly(f);
+                }
+                if let Err(e) = replace_with_hardlink(kept, f) {
+                    if e.kind() == std::io::ErrorKind::CrossesDevices {
+                        eprintln!("Cannot hardlink across filesystems, keeping {:?}", f);
+                    } else {
+                        eprintln!("Unable to replace file: {}", e);
+                    }
+                    continue;
+                }
+            }
+            (Some(ReplaceMode::Reflink), true) => writeln!(
+                out,
+                " Would reflink {:?} to {:?}",
+                report.display(f),
+                report.display(kept)
+            )?,
+            (Some(ReplaceMode::Reflink), false) => {
+                writeln!(out, " {} {:?}", "Cloning".yellow(), report.display(f))?;
+                if let Err(e) = replace_with_reflink(kept, f) {
+                    match e.kind() {
+                        std::io::ErrorKind::CrossesDevices => {
+                            eprintln!("Cannot reflink across filesystems, keeping {:?}", f)
+                        }
+                        std::io::ErrorKind::Unsupported => {
+                            eprintln!("Cannot reflink {:?}: {}, keeping it", f, e)
+                        }
+                        _ => eprintln!("Unable to replace file: {}", e),
+                    }
+                    continue;
+                }
+            }
+        }
+        summary.files += 1;
+        summary.bytes += v.size;
+    }
+    // only decisions that were carried out are replayed
+    if let (Some(log), false) = (&fix.record_decisions, fix.dry_run) {
+        let kept = (0..v.paths.len())
+            .filter(|idx| !plan.remove.contains(idx))
+            .map(|idx| v.paths[idx].clone())
+            .collect();
+        if let Err(e) = log.record(&plan.hash, kept) {
+            eprintln!(
+                "Warning: unable to record the decision in {:?}: {e}",
+                log.path
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether removing `f` is expected to succeed before any file of its
+/// group is touched, and returns whether to go ahead with it.
+///
+/// Files in read-only directories are skipped. Read-only files are skipped
+/// as well unless `--force` is given or removing them is confirmed at the
+/// prompt.
+fn check_removable(
+    f: &std::path::Path,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    out: &mut dyn Write,
+) -> Result<bool, std::io::Error> {
+    let shown = report.display(f);
+    let readonly = |path: &std::path::Path| {
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly())
+    };
+    if f.parent().is_some_and(readonly) {
+        eprintln!("Warning: skipping {shown:?}, its directory is read-only");
+        return Ok(false);
+    }
+    if fix.force || !readonly(f) {
+        return Ok(true);
+    }
+    if fix.dry_run || !fix.interactive {
+        eprintln!("Warning: skipping read-only file {shown:?} (use --force to remove it)");
+        return Ok(false);
+    }
+    write!(out, "{shown:?} is read-only. Remove it anyway? [y/N] ")?;
+    out.flush()?;
+    let answer = read_input_line()?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Makes `f` writable for the owner if it is read-only, so that it can be
+/// removed on every platform.
+fn clear_readonly(f: &std::path::Path) {
+    let Ok(metadata) = std::fs::symlink_metadata(f) else {
+        return;
+    };
+    let mut permissions = metadata.permissions();
+    if !permissions.readonly() {
+        return;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    // only clears the read-only attribute on these platforms
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    if let Err(e) = std::fs::set_permissions(f, permissions) {
+        eprintln!("Warning: unable to make {f:?} writable: {e}");
+    }
+}
+
+/// Deletes `f` or moves it to the trash, or only reports it in a dry run.
+///
+/// Returns whether the file was (or would be) removed.
+fn remove_file(
+    f: &std::path::Path,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<bool, std::io::Error> {
+    if let Some(suffix) = &fix.rename_suffix {
+        return rename_file(f, suffix, report, fix, out);
+    }
+    let shown = report.display(f);
+    if let Some(script) = &fix.script {
+        writeln!(out, " Adding {:?} to the script", shown)?;
+        script.remove(f)?;
+        return Ok(true);
+    }
+    match (fix.trash, fix.dry_run) {
+        (true, true) => writeln!(out, " Would move {:?} to trash", shown)?,
+        (true, false) => {
+            writeln!(out, " {} {:?}", "Trashing".yellow(), shown)?;
+            if let Err(e) = trash::delete(f) {
+                eprintln!("Unable to move file to trash, keeping it: {}", e);
+                return Ok(false);
+            }
+        }
+        (false, true) => writeln!(out, " Would delete {:?}", shown)?,
+        (false, false) => {
+            writeln!(out, " {} {:?}", "Deleting".red(), shown)?;
+            clear_readonly(f);
+            if let Err(e) = std::fs::remove_file(f) {
+                eprintln!("Unable to remove file: {}", e);
+                return Ok(false);
+            }
+        }
+    }
+    if !fix.dry_run {
+        summary
+            .touched_dirs
+            .extend(f.parent().map(|p| p.to_path_buf()));
+    }
+    Ok(true)
+}
+
+/// Removes the files in the directory `dir` that have an identical copy at
+/// the same place in `kept`, then the directories left empty.
+///
+/// The directories were only compared by the files that passed the filters,
+/// so anything else, like hidden or excluded files, is kept along with the
+/// directories holding it.
+fn remove_dir_copy(
+    dir: &std::path::Path,
+    kept: &std::path::Path,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let mut unmatched = 0;
+    remove_dir_files(dir, kept, report, fix, summary, &mut unmatched, out)?;
+    if unmatched > 0 {
+        writeln!(
+            out,
+            " Keeping {} {} in {:?} without a copy in {:?}",
+            unmatched,
+            if unmatched == 1 { "entry" } else { "entries" },
+            report.display(dir),
+            report.display(kept)
+        )?;
+    }
+    Ok(())
+}
+
+/// Does the work of [`remove_dir_copy`] for one level of the tree, counting
+/// the entries that are kept in `unmatched`.
+fn remove_dir_files(
+    dir: &std::path::Path,
+    kept: &std::path::Path,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    summary: &mut FixSummary,
+    unmatched: &mut usize,
+    out: &mut dyn Write,
 ) -> Result<(), std::io::Error> {
-    let reader = std::fs::read_dir(path)?;
-    for it in reader {
-        let entry = it?;
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Unable to read directory {:?}: {}", dir, e);
+            *unmatched += 1;
+            return Ok(());
+        }
+    };
+    for entry in entries {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(interrupted_error());
+        }
+        let entry = entry?;
         let path = entry.path();
-        if path.is_dir() {
-            scan_rec(&path, filemap)?;
-        } else if path.is_file() {
-            let (hash, size) = hash_file(&path)?;
-            if filemap.contains_key(&hash) {
-                filemap.get_mut(&hash).unwrap().add_path(path);
-            } else {
-                filemap.insert(hash, FileInfo::new(path, size));
-            }
+        let copy = kept.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            remove_dir_files(&path, &copy, report, fix, summary, unmatched, out)?;
+            continue;
+        }
+        let is_copy = file_type.is_file()
+            && std::fs::symlink_metadata(&copy).is_ok_and(|m| m.is_file())
+            && files_equal(&path, &copy, HASH_CHUNK_SIZE).unwrap_or(false);
+        if !is_copy {
+            *unmatched += 1;
+            continue;
+        }
+        let shown = report.display(&path);
+        let size = entry.metadata().map_or(0, |m| m.len());
+        if fix.dry_run {
+            writeln!(out, " Would delete {:?}", shown)?;
+            summary.files += 1;
+            summary.bytes += size;
+            continue;
         }
+        writeln!(out, " {} {:?}", "Deleting".red(), shown)?;
+        if fix.force {
+            clear_readonly(&path);
+        }
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("Unable to remove file: {}", e);
+            *unmatched += 1;
+            continue;
+        }
+        summary.files += 1;
+        summary.bytes += size;
+    }
+    // directories that still hold kept entries are not empty and stay
+    if !fix.dry_run && std::fs::remove_dir(dir).is_ok() {
+        summary
+            .touched_dirs
+            .extend(dir.parent().map(|p| p.to_path_buf()));
     }
     Ok(())
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct FileInfo {
-    paths: Vec<std::path::PathBuf>,
-    size: usize,
+/// Renames `f` by appending `suffix`, or a counter and `suffix` if that name
+/// is taken.
+///
+/// Files that already end with `suffix` were tagged by an earlier run and are
+/// left alone, instead of getting the suffix twice.
+fn rename_file(
+    f: &std::path::Path,
+    suffix: &str,
+    report: &ReportOptions,
+    fix: &FixOptions,
+    out: &mut dyn Write,
+) -> Result<bool, std::io::Error> {
+    if f.as_os_str()
+        .as_encoded_bytes()
+        .ends_with(suffix.as_bytes())
+    {
+        writeln!(out, " Skipping {:?}, already renamed", report.display(f))?;
+        return Ok(false);
+    }
+    let renamed = (1..)
+        .map(|n| {
+            let mut name = f.as_os_str().to_owned();
+            if n > 1 {
+                name.push(format!(".{n}"));
+            }
+            name.push(suffix);
+            std::path::PathBuf::from(name)
+        })
+        .find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+        .expect("a free name exists");
+    let (shown, shown_renamed) = (report.display(f), report.display(&renamed));
+    if fix.dry_run {
+        writeln!(out, " Would rename {:?} to {:?}", shown, shown_renamed)?;
+        return Ok(true);
+    }
+    writeln!(
+        out,
+        " {} {:?} to {:?}",
+        "Renaming".yellow(),
+        shown,
+        shown_renamed
+    )?;
+    if let Err(e) = std::fs::rename(f, &renamed) {
+        eprintln!("Unable to rename file: {}", e);
+        return Ok(false);
+    }
+    Ok(true)
 }
 
-impl FileInfo {
-    fn new(path: std::path::PathBuf, size: usize) -> Self {
-        Self {
-            paths: Vec::from(&[path]),
-            size,
+/// Lists the empty files found by the scan and removes them when fixing.
+///
+/// Files below a reference directory are listed but never removed.
+fn handle_empty_files(
+    mut files: Vec<std::path::PathBuf>,
+    report: &ReportOptions,
+    fix: Option<&FixOptions>,
+    summary: &mut FixSummary,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    if report.format != OutputFormat::Text || files.is_empty() {
+        return Ok(());
+    }
+    files.sort();
+    writeln!(out, "Empty files:")?;
+    if let Some(script) = fix.and_then(|fix| fix.script.as_ref()) {
+        script.comment("empty files")?;
+    }
+    let mut removed = 0;
+    for f in &files {
+        let Some(fix) = fix.filter(|_| !report.is_reference(f)) else {
+            writeln!(out, " {:?}", report.display(f))?;
+            continue;
+        };
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(interrupted_error());
+        }
+        if check_removable(f, report, fix, out)? && remove_file(f, report, fix, summary, out)? {
+            removed += 1;
+        }
+    }
+    if report.quiet {
+        return Ok(());
+    }
+    match fix {
+        None => writeln!(out, "Found {} empty files", files.len()),
+        Some(fix) if fix.rename_suffix.is_some() && fix.dry_run => {
+            writeln!(out, "Would rename {removed} empty files")
+        }
+        Some(fix) if fix.rename_suffix.is_some() => writeln!(out, "Renamed {removed} empty files"),
+        Some(fix) if fix.script.is_some() => {
+            writeln!(
+                out,
+                "Wrote the removal of {removed} empty files to the script"
+            )
         }
+        Some(fix) if fix.dry_run => writeln!(out, "Would remove {removed} empty files"),
+        Some(_) => writeln!(out, "Removed {removed} empty files"),
     }
+}
 
-    fn add_path(self: &mut Self, path: std::path::PathBuf) {
-        self.paths.push(path);
+/// Replaces `duplicate` by a hardlink to `kept`.
+///
+/// The link is created under a temporary name first and then renamed over
+/// the duplicate, so the duplicate stays untouched if linking fails.
+fn replace_with_hardlink(
+    kept: &std::path::Path,
+    duplicate: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    let mut temp_name = duplicate.as_os_str().to_owned();
+    temp_name.push(".fdc-link");
+    let temp_path = std::path::PathBuf::from(temp_name);
+    std::fs::hard_link(kept, &temp_path)?;
+    if let Err(e) = std::fs::rename(&temp_path, duplicate) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
     }
+    Ok(())
 }
 
-fn hash_file(path: &std::path::Path) -> Result<(Hash, usize), std::io::Error> {
-    let mut hasher = sha_256::Sha256::new();
-    let data = std::fs::read(path)?;
-    let hash = hasher.digest(&data);
-    Ok((Hash::new(&hash), data.len()))
+/// Replaces `duplicate` by a reflink copy of `kept`.
+///
+/// Like [`replace_with_hardlink`], the copy is created under a temporary
+/// name first. It gets the permissions and modification time of the
+/// duplicate, which remain independent of the kept file.
+fn replace_with_reflink(
+    kept: &std::path::Path,
+    duplicate: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    let metadata = std::fs::metadata(duplicate)?;
+    let mut temp_name = duplicate.as_os_str().to_owned();
+    temp_name.push(".fdc-reflink");
+    let temp_path = std::path::PathBuf::from(temp_name);
+    reflink(kept, &temp_path)?;
+    let result = std::fs::File::options()
+        .write(true)
+        .open(&temp_path)
+        .and_then(|file| file.set_modified(metadata.modified()?))
+        .and_then(|()| std::fs::set_permissions(&temp_path, metadata.permissions()))
+        .and_then(|()| std::fs::rename(&temp_path, duplicate));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
-struct Hash {
-    hash: [u8; 32],
+/// Creates `dst` as a copy-on-write clone of `src`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(src: &std::path::Path, dst: &std::path::Path) -> Result<(), std::io::Error> {
+    use std::os::fd::AsRawFd;
+    let source = std::fs::File::open(src)?;
+    let target = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    drop(target);
+    let _ = std::fs::remove_file(dst);
+    Err(match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::EINVAL | libc::ENOTTY) => reflink_unsupported(),
+        _ => e,
+    })
 }
 
-impl Hash {
-    fn new(h: &[u8; 32]) -> Self {
-        Self { hash: *h }
+/// Creates `dst` as a copy-on-write clone of `src`.
+#[cfg(target_os = "macos")]
+fn reflink(src: &std::path::Path, dst: &std::path::Path) -> Result<(), std::io::Error> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = |path: &std::path::Path| {
+        std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    };
+    let (src, dst) = (c_path(src)?, c_path(dst)?);
+    // SAFETY: both paths are valid NUL-terminated strings
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } == 0 {
+        return Ok(());
     }
+    let e = std::io::Error::last_os_error();
+    Err(match e.raw_os_error() {
+        Some(libc::ENOTSUP) => reflink_unsupported(),
+        _ => e,
+    })
+}
+
+/// Creates `dst` as a copy-on-write clone of `src`.
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn reflink(_src: &std::path::Path, _dst: &std::path::Path) -> Result<(), std::io::Error> {
+    Err(reflink_unsupported())
+}
+
+fn reflink_unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the file system does not support reflinks",
+    )
 }
 
-impl std::fmt::Display for Hash {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        for i in 0..32 {
-            write!(f, "{:02x}", self.hash[i])?;
+/// Asks the user which files of the group to keep.
+///
+/// Returns the zero-based indices of the files to keep, or `None` to keep all.
+/// Entering `o N` opens file N with the default application and asks again.
+fn prompt_indices_to_keep(v: &FileInfo) -> Result<Choice, std::io::Error> {
+    Ok(loop {
+        print!(
+            "Select files to keep, separated by commas \
+             (0 or s to keep all, n/old to keep the newest/oldest, o N to open file N, \
+             q to stop fixing): "
+        );
+        std::io::stdout().flush()?;
+        let str_indices_to_keep = read_input_line()?;
+        if str_indices_to_keep.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "stdin closed while waiting for a selection",
+            ));
         }
-        Ok(())
-    }
+        let shortcut = match str_indices_to_keep.trim() {
+            "s" | "skip" => break Choice::KeepAll,
+            "q" | "quit" => break Choice::Quit,
+            "n" | "newest" => Some(KeepStrategy::Newest),
+            "old" | "oldest" => Some(KeepStrategy::Oldest),
+            _ => None,
+        };
+        if let Some(strategy) = shortcut {
+            match select_index_to_keep(v, strategy) {
+                Some(index) => break Choice::Keep(vec![index]),
+                None => {
+                    println!("Unable to determine the modification times.");
+                    continue;
+                }
+            }
+        }
+        if let Some(index) = str_indices_to_keep.trim().strip_prefix('o') {
+            match index.trim().parse::<usize>() {
+                Ok(index) if (1..=v.paths.len()).contains(&index) => {
+                    if let Err(e) = open::that_detached(&v.paths[index - 1]) {
+                        eprintln!("Warning: unable to open {:?}: {}", v.paths[index - 1], e);
+                    }
+                }
+                _ => println!("Invalid index."),
+            }
+            continue;
+        }
+        let Ok(candidates) = str_indices_to_keep
+            .split(',')
+            .map(|s| s.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            println!("Invalid input.");
+            continue;
+        };
+        if candidates.iter().any(|&idx| idx > v.paths.len()) {
+            println!("Invalid index.");
+        } else if candidates.contains(&0) {
+            if candidates.len() == 1 {
+                break Choice::KeepAll;
+            }
+            println!("0 cannot be combined with other indices.");
+        } else {
+            let mut indices: Vec<usize> = candidates.iter().map(|idx| idx - 1).collect();
+            indices.sort_unstable();
+            indices.dedup();
+            break Choice::Keep(indices);
+        }
+    })
 }
 
-fn handle_duplicates(
-    file_map: HashMap<Hash, FileInfo>,
-    do_fix: bool,
+/// Removes the directories in `dirs` that are now empty, along with parents
+/// that become empty in turn. The scan roots themselves are never removed.
+fn prune_empty_dirs(
+    dirs: &std::collections::BTreeSet<std::path::PathBuf>,
+    roots: &[std::path::PathBuf],
+    out: &mut dyn Write,
 ) -> Result<(), std::io::Error> {
-    for (k, v) in file_map.iter().filter(|(_, v)| v.paths.len() > 1) {
-        println!("Hash set {} (filesize: {} bytes):", k, v.size);
-        for (idx, f) in v.paths.iter().enumerate() {
-            println!(" {} - {:?}", idx + 1, f);
+    // visit deeper directories first so that emptied parents are seen later
+    let mut pending: Vec<&std::path::Path> = dirs.iter().map(|d| d.as_path()).collect();
+    pending.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    let mut index = 0;
+    while let Some(&dir) = pending.get(index) {
+        index += 1;
+        if roots.iter().any(|root| root == dir) || !roots.iter().any(|root| dir.starts_with(root)) {
+            continue;
         }
-        if do_fix {
-            fix_duplicates(v)?;
+        // remove_dir only succeeds on an empty directory
+        match std::fs::remove_dir(dir) {
+            Ok(()) => {
+                writeln!(out, " Removed empty directory {:?}", dir)?;
+                if let Some(parent) = dir.parent() {
+                    if !pending.contains(&parent) {
+                        pending.push(parent);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {}
+            Err(e) => eprintln!("Unable to remove directory {:?}: {}", dir, e),
         }
     }
     Ok(())
 }
 
-fn fix_duplicates(v: &FileInfo) -> Result<(), std::io::Error> {
-    if let Some(index_to_keep) = loop {
-        print!("Select one to keep (0 to keep all): ");
-        std::io::stdout().flush()?;
-        let mut str_index_to_keep = String::new();
-        std::io::stdin().read_line(&mut str_index_to_keep)?;
-        if let Ok(candidate_index_to_keep) = str_index_to_keep.trim().parse::<usize>() {
-            if candidate_index_to_keep <= v.paths.len() {
-                break if candidate_index_to_keep == 0 {
-                    None
-                } else {
-                    Some(candidate_index_to_keep - 1)
-                };
-            } else {
-                println!("Invalid index.")
+/// Exit code used when `--fail-on-duplicates` is given and duplicates exist.
+const EXIT_DUPLICATES_FOUND: u8 = 2;
+
+/// Exit code used when files or directories were skipped due to errors.
+const EXIT_SCAN_ERRORS: u8 = 3;
+
+/// Exit code used when a target or reference path is not a directory, or a
+/// target is inside a reference directory.
+const EXIT_USAGE: u8 = 64;
+
+/// Exit code used when the fix phase was stopped by Ctrl-C.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Resolves `path` to an absolute path without `.`, `..` or symbolic links.
+///
+/// Falls back to the path made absolute against the working directory if it
+/// cannot be resolved, e.g. because it vanished.
+fn absolute_path(path: &std::path::Path) -> std::path::PathBuf {
+    std::fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Replaces the paths of each group by their absolute form. Paths resolving
+/// to the same file are only kept once, and groups left with a single path
+/// are dropped.
+fn absolute_groups<T>(groups: &mut Vec<T>, info: impl Fn(&mut T) -> &mut FileInfo) {
+    groups.retain_mut(|group| {
+        let v = info(group);
+        let mut paths: Vec<std::path::PathBuf> = v.paths.iter().map(|p| absolute_path(p)).collect();
+        paths.sort();
+        paths.dedup();
+        v.paths = paths;
+        v.paths.len() > 1
+    });
+}
+
+/// Checks that all `dirs` exist and are directories before scanning starts.
+fn validate_dirs(dirs: &[std::path::PathBuf]) -> Result<(), String> {
+    for dir in dirs {
+        match std::fs::metadata(dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                return Err(format!(
+                    "target is not a directory: {dir:?} (use --from-stdin to check single files)"
+                ))
             }
-        } else {
-            println!("Invalid input.");
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(format!("target directory does not exist: {dir:?}"))
+            }
+            Err(e) => return Err(format!("unable to access target directory {dir:?}: {e}")),
         }
-    } {
-        for (idx, f) in v.paths.iter().enumerate() {
-            if idx != index_to_keep {
-                println!(" {} {:?}", "Deleting".red(), f);
-                if let Err(e) = std::fs::remove_file(f) {
-                    eprintln!("Unable to remove file: {}", e);
+    }
+    Ok(())
+}
+
+/// Checks that no target is inside one of the `reference` directories.
+///
+/// Such a target would only be scanned as part of the reference, so none of
+/// its files could be reported as copies.
+fn validate_references(
+    targets: &[std::path::PathBuf],
+    reference: &[std::path::PathBuf],
+) -> Result<(), String> {
+    for target in targets {
+        let canonical = std::fs::canonicalize(target)
+            .map_err(|e| format!("unable to access target directory {target:?}: {e}"))?;
+        for r in reference {
+            let canonical_reference = std::fs::canonicalize(r)
+                .map_err(|e| format!("unable to access reference directory {r:?}: {e}"))?;
+            if canonical.starts_with(&canonical_reference) {
+                return Err(format!(
+                    "target directory {target:?} is inside the reference directory {r:?}"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of a successful run.
+struct RunSummary {
+    /// Whether duplicates were found and left in place, i.e. not fixing.
+    duplicates_found: bool,
+    /// Whether the fix phase was stopped by Ctrl-C.
+    interrupted: bool,
+    /// Number of groups that were reported.
+    groups: usize,
+    /// Duplicate groups that were reported, with their paths sorted.
+    duplicates: Vec<(Hash, FileInfo)>,
+    /// Copies beyond the first one in each group.
+    redundant_files: usize,
+    reclaimable_bytes: u64,
+    stats: ScanStats,
+}
+
+/// What to look for duplicates in.
+enum Targets {
+    /// Directory trees to walk.
+    Dirs(Vec<std::path::PathBuf>),
+    /// Exactly these files, e.g. read from stdin.
+    Files(Vec<std::path::PathBuf>),
+}
+
+/// Reads a list of paths separated by NUL bytes, or by newlines if the input
+/// contains no NUL byte.
+fn read_path_list(
+    input: &mut dyn std::io::Read,
+) -> Result<Vec<std::path::PathBuf>, std::io::Error> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let separator = if data.contains(&0) { b'\0' } else { b'\n' };
+    Ok(data
+        .split(|&b| b == separator)
+        .map(|path| path.strip_suffix(b"\r").unwrap_or(path))
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+/// Scans for duplicates and reports or fixes them.
+fn run(
+    targets: &Targets,
+    options: &ScanOptions,
+    report: &ReportOptions,
+    fix: Option<&FixOptions>,
+    output: Option<&std::path::Path>,
+) -> Result<RunSummary, std::io::Error> {
+    // open the report file first so that a bad path fails before the scan
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+    let streamed = streams_groups(targets, options, report, fix);
+    let (mut duplicates, mut stats) = match targets {
+        Targets::Dirs(dirs) if report.list_only => {
+            let (mut files, stats) = hash_all_files(dirs, options)?;
+            if report.absolute {
+                for f in files.iter_mut() {
+                    f.path = absolute_path(&f.path);
                 }
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+            }
+            write_listing(&files, report, &mut out)?;
+            out.flush()?;
+            print_errors(&stats);
+            return Ok(RunSummary {
+                duplicates_found: false,
+                interrupted: false,
+                groups: 0,
+                duplicates: Vec::new(),
+                redundant_files: 0,
+                reclaimable_bytes: 0,
+                stats,
+            });
+        }
+        Targets::Dirs(dirs) if streamed => stream_duplicates(dirs, options, report, &mut out)?,
+        Targets::Files(files) => find_duplicates_among(files, options)?,
+        Targets::Dirs(dirs) if report.dirs => find_duplicate_dirs(dirs, options)?,
+        Targets::Dirs(dirs) if report.by_name_size => {
+            let (mut candidates, mut stats) = find_name_size_candidates(dirs, options)?;
+            if report.absolute {
+                absolute_groups(&mut candidates, |v| v);
+            }
+            candidates.retain(|v| v.paths.iter().any(|p| report.matches_filter(p)));
+            if fix.is_none() {
+                write_candidates(&candidates, report, &mut out)?;
+                out.flush()?;
+                print_errors(&stats);
+                return Ok(RunSummary {
+                    duplicates_found: !candidates.is_empty(),
+                    interrupted: false,
+                    groups: candidates.len(),
+                    duplicates: Vec::new(),
+                    redundant_files: candidates.iter().map(|v| v.paths.len() - 1).sum(),
+                    reclaimable_bytes: candidates
+                        .iter()
+                        .map(|v| (v.paths.len() as u64 - 1) * v.size)
+                        .sum(),
+                    stats,
+                });
+            }
+            // only files with identical content may be changed
+            let files: Vec<std::path::PathBuf> =
+                candidates.into_iter().flat_map(|v| v.paths).collect();
+            let (duplicates, hash_stats) = find_duplicates_among(&files, options)?;
+            stats.errors.extend(hash_stats.errors);
+            stats.vanished += hash_stats.vanished;
+            stats.bytes_hashed += hash_stats.bytes_hashed;
+            (duplicates, stats)
+        }
+        Targets::Dirs(dirs) => match &report.manifest {
+            Some(manifest) => find_duplicates_of_manifest(manifest, dirs, options)?,
+            None if report.reference.is_empty() => find_duplicates_in(dirs, options)?,
+            None => find_duplicates_of_reference(&report.reference, dirs, options)?,
+        },
+    };
+    // label the groups that are not known to be byte-identical in the report
+    let labeled_report;
+    let report = if stats.normalized.is_empty() && stats.likely.is_empty() {
+        report
+    } else {
+        labeled_report = ReportOptions {
+            normalized: std::mem::take(&mut stats.normalized),
+            likely: std::mem::take(&mut stats.likely),
+            ..report.clone()
+        };
+        &labeled_report
+    };
+    if report.absolute {
+        if !streamed {
+            absolute_groups(&mut duplicates, |(_, v)| v);
+        }
+        for path in stats.empty_files.iter_mut() {
+            *path = absolute_path(path);
+        }
+    }
+    if !streamed {
+        duplicates.retain(|(_, v)| v.paths.iter().any(|p| report.matches_filter(p)));
+    }
+    stats.empty_files.retain(|p| report.matches_filter(p));
+    // without --fix-normalized only the byte-identical copies are fixed
+    let split_report;
+    let report = if fix.is_some_and(|fix| !fix.fix_normalized) && !report.normalized.is_empty() {
+        split_report = ReportOptions {
+            byte_identical: duplicates
+                .iter()
+                .filter(|(k, _)| report.normalized.contains(k))
+                .map(|(k, v)| (*k, byte_identical_groups(v)))
+                .collect(),
+            ..report.clone()
+        };
+        &split_report
+    } else {
+        report
+    };
+    if let Some(top) = report.top {
+        // stable, so groups freeing the same space keep their order
+        duplicates.sort_by_key(|(k, v)| std::cmp::Reverse(report.reclaimable_bytes(k, v)));
+        duplicates.truncate(top);
+    }
+    if report.group_by_ext {
+        // groups without extension last, the order within a section is kept
+        for (_, v) in duplicates.iter_mut() {
+            v.paths.sort();
+        }
+        duplicates.sort_by_key(|(_, v)| {
+            let ext = extension(v);
+            (ext.is_none(), ext)
+        });
+    }
+    let redundant_files = duplicates
+        .iter()
+        .map(|(k, v)| report.redundant_files(k, v))
+        .sum();
+    let reclaimable_bytes = duplicates
+        .iter()
+        .map(|(k, v)| report.reclaimable_bytes(k, v))
+        .sum();
+    let duplicates_found = fix.is_none() && !duplicates.is_empty();
+    let mut fixed = if !streamed {
+        handle_duplicates(&mut duplicates, report, fix, &mut out)?
+    } else {
+        // the groups are already written
+        let fixed = FixSummary::default();
+        if report.format == OutputFormat::Text && !report.quiet {
+            write_summary(&duplicates, report, fix, &fixed, &mut out)?;
+        }
+        fixed
+    };
+    if report.histogram {
+        if report.format == OutputFormat::Text {
+            write_histogram(&duplicates, report, &mut out)?;
+        } else {
+            // keep the machine-readable report parseable
+            write_histogram(&duplicates, report, &mut std::io::stderr())?;
+        }
+    }
+    if !fixed.interrupted {
+        let empty_files = std::mem::take(&mut stats.empty_files);
+        match handle_empty_files(empty_files, report, fix, &mut fixed, &mut out) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                writeln!(
+                    out,
+                    "\nInterrupted, remaining empty files were left untouched."
+                )?;
+                fixed.interrupted = true;
             }
+            result => result?,
+        }
+    }
+    if fix.is_some_and(|fix| fix.prune_empty_dirs) && !fixed.interrupted {
+        let roots = match targets {
+            Targets::Dirs(dirs) => dirs.as_slice(),
+            Targets::Files(_) => &[],
+        };
+        prune_empty_dirs(&fixed.touched_dirs, roots, &mut out)?;
+    }
+    if let Some(script) = fix.and_then(|fix| fix.script.as_ref()) {
+        script.flush()?;
+    }
+    out.flush()?;
+    print_errors(&stats);
+    if stats.vanished > 0 && !report.quiet {
+        eprintln!(
+            "{} files vanished or changed during the scan",
+            stats.vanished
+        );
+    }
+    Ok(RunSummary {
+        duplicates_found,
+        interrupted: fixed.interrupted,
+        groups: duplicates.len(),
+        duplicates,
+        redundant_files,
+        reclaimable_bytes,
+        stats,
+    })
+}
+
+/// Lists the entries skipped due to errors on stderr, after the report.
+fn print_errors(stats: &ScanStats) {
+    if stats.errors.is_empty() {
+        return;
+    }
+    let mut errors: Vec<_> = stats.errors.iter().collect();
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    eprintln!("Errors:");
+    for error in errors {
+        eprintln!(" {:?}: {}", error.path, error.message);
+    }
+    eprintln!(
+        "{} files or directories were skipped due to errors",
+        stats.errors.len()
+    );
+}
+
+/// How long `--benchmark` keeps hashing.
+const BENCHMARK_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Size of the in-memory buffer hashed by `--benchmark` unless `--size` is given.
+const DEFAULT_BENCHMARK_SIZE: u64 = 64 << 20;
+
+/// Measures hashing throughput and prints it as a single `key=value` line.
+fn run_benchmark(
+    file: Option<&std::path::Path>,
+    size: u64,
+    algorithm: HashAlgorithm,
+    jobs: usize,
+    chunk_size: usize,
+    mmap: bool,
+) -> Result<(), std::io::Error> {
+    let buffer;
+    let source = match file {
+        Some(path) => BenchmarkSource::File(path),
+        None => {
+            // pseudo-random content, so that the hash cannot take shortcuts
+            let mut state = 0x2545_f491_4f6c_dd1d_u64;
+            buffer = (0..size)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect::<Vec<u8>>();
+            BenchmarkSource::Buffer(&buffer)
         }
+    };
+    let result = benchmark_hashing(
+        source,
+        algorithm,
+        jobs,
+        chunk_size,
+        mmap,
+        BENCHMARK_DURATION,
+    )?;
+    let algorithm = clap::ValueEnum::to_possible_value(&algorithm)
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    println!(
+        "benchmark algorithm={} jobs={} bytes={} seconds={:.3} mb_per_s={:.1}",
+        algorithm,
+        jobs,
+        result.bytes,
+        result.elapsed.as_secs_f64(),
+        result.bytes_per_second() / 1e6
+    );
+    Ok(())
+}
+
+fn print_stats(summary: &RunSummary, elapsed: std::time::Duration) {
+    eprintln!("Files scanned:    {}", summary.stats.files_scanned);
+    eprintln!(
+        "Bytes hashed:     {}",
+        format_bytes(summary.stats.bytes_hashed)
+    );
+    eprintln!("Duplicate groups: {}", summary.duplicates.len());
+    eprintln!("Elapsed time:     {:.2?}", elapsed);
+}
+
+/// Writes how many groups have a given number of copies and how many free a
+/// given amount of space.
+fn write_histogram(
+    duplicates: &[(Hash, FileInfo)],
+    report: &ReportOptions,
+    out: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let copies = [
+        ("2 copies", 2..3),
+        ("3 copies", 3..4),
+        ("4 copies", 4..5),
+        ("5-9 copies", 5..10),
+        ("10+ copies", 10..usize::MAX),
+    ];
+    writeln!(out, "Groups by number of copies:")?;
+    for (label, range) in copies {
+        let groups = duplicates
+            .iter()
+            .filter(|(_, v)| range.contains(&v.paths.len()))
+            .count();
+        writeln!(out, " {label:<16} {groups:>8}")?;
+    }
+    let space = [
+        ("< 1 KiB", 0..1 << 10),
+        ("1 KiB - 1 MiB", 1 << 10..1 << 20),
+        ("1 MiB - 1 GiB", 1 << 20..1 << 30),
+        (">= 1 GiB", 1 << 30..u64::MAX),
+    ];
+    writeln!(out, "Groups by reclaimable space:")?;
+    for (label, range) in space {
+        let groups = duplicates
+            .iter()
+            .filter(|(k, v)| range.contains(&report.reclaimable_bytes(k, v)))
+            .count();
+        writeln!(out, " {label:<16} {groups:>8}")?;
     }
     Ok(())
 }
 
-fn run(target_dir: &std::path::Path, do_fix: bool) -> Result<(), std::io::Error> {
-    handle_duplicates(scan_on_directory(target_dir)?, do_fix)
+/// Prints the outcome of the run as a single line of JSON on stderr.
+fn print_summary_json(summary: &RunSummary) {
+    let summary = serde_json::json!({
+        "groups": summary.groups,
+        "redundant_files": summary.redundant_files,
+        "reclaimable_bytes": summary.reclaimable_bytes,
+        "files_scanned": summary.stats.files_scanned,
+        "errors": summary.stats.errors.len(),
+    });
+    eprintln!("{summary}");
+}
+
+/// Parses the `--sample` value, a number in `(0, 1]`.
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        Ok(_) => Err("fraction must be greater than 0 and at most 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses the `--rename-suffix` value, which must be a non-empty part of a
+/// file name.
+fn parse_rename_suffix(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(std::path::is_separator) {
+        return Err("suffix must be non-empty and must not contain path separators".to_string());
+    }
+    Ok(s.to_string())
+}
+
+/// Parses the `--min-copies` value, as a group needs at least two files.
+fn parse_min_copies(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>().map_err(|e| e.to_string())? {
+        n if n < 2 => Err("a group has at least 2 copies".to_string()),
+        n => Ok(n),
+    }
+}
+
+/// Parses the `--partial-hash` size, which must not be zero.
+fn parse_partial_hash(s: &str) -> Result<u64, String> {
+    match parse_size(s)? {
+        0 => Err("at least one byte must be hashed".to_string()),
+        n => Ok(n),
+    }
+}
+
+/// Parses the `--filter` pattern.
+fn parse_filter(s: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(s).map_err(|e| format!("invalid regular expression: {e}"))
+}
+
+/// Parses the `--chunk-size` value, which must not be zero.
+fn parse_chunk_size(s: &str) -> Result<u64, String> {
+    match parse_size(s)? {
+        0 => Err("chunk size must be positive".to_string()),
+        size => Ok(size),
+    }
+}
+
+/// Limits `size` to the supported chunk sizes, with a warning if it is out
+/// of range.
+fn clamp_chunk_size(size: u64) -> usize {
+    let clamped = usize::try_from(size)
+        .unwrap_or(usize::MAX)
+        .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    if clamped as u64 != size {
+        eprintln!(
+            "Warning: chunk size {} is out of range, using {}",
+            format_bytes(size),
+            format_bytes(clamped as u64)
+        );
+    }
+    clamped
+}
+
+/// Parses a human-readable size like `512`, `10MB` or `500KiB` into bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {s:?}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown size unit: {unit:?}")),
+    };
+    let size = (number * multiplier as f64).round();
+    if size >= u64::MAX as f64 {
+        return Err(format!("size too large: {s:?}"));
+    }
+    Ok(size as u64)
+}
+
+/// Parses a duration like `30d`, `12h` or `90s`.
+fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {s:?}"))?;
+    let seconds: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "" => return Err(format!("missing duration unit (s, m, h, d or w): {s:?}")),
+        _ => return Err(format!("unknown duration unit: {unit:?}")),
+    };
+    std::time::Duration::try_from_secs_f64(number * seconds as f64)
+        .map_err(|_| format!("duration too long: {s:?}"))
+}
+
+fn parse_exclude(s: &str) -> Result<String, String> {
+    duplicate_checker::exclude_glob(s)
+        .map(|_| s.to_string())
+        .map_err(|e| e.to_string())
 }
 
 #[derive(clap::Parser, Debug)]
 #[command(version, about)]
 struct Cli {
-    /// Path of the directory to scan
-    target_path: std::path::PathBuf,
+    /// Paths of the directories to scan
+    #[arg(required_unless_present_any = ["from_stdin", "benchmark"])]
+    target_paths: Vec<std::path::PathBuf>,
+    /// Check exactly the files listed on stdin (separated by newlines or NUL bytes) instead of
+    /// scanning directories
+    #[arg(long, conflicts_with_all = ["target_paths", "reference"])]
+    from_stdin: bool,
     /// Fix duplicates by selecting one file to keep
-    #[arg(short = 'f', long)]
+    #[arg(short = 'f', long, conflicts_with = "format")]
     do_fix: bool,
+    /// Automatically keep one file per group instead of prompting (requires --do-fix)
+    #[arg(long, value_enum, requires = "do_fix")]
+    keep: Option<KeepStrategy>,
+    /// Show which files would be deleted without deleting them (requires --do-fix)
+    #[arg(long, requires = "do_fix")]
+    dry_run: bool,
+    /// Replace redundant copies instead of deleting them (requires --do-fix)
+    #[arg(long, value_enum, requires = "do_fix")]
+    replace_with: Option<ReplaceMode>,
+    /// Move duplicates to the trash instead of deleting them (requires --do-fix)
+    #[arg(long, requires = "do_fix", conflicts_with = "replace_with")]
+    trash: bool,
+    /// Rename duplicates by appending SUFFIX (and a counter if the name is taken) instead of
+    /// deleting them; files already ending in SUFFIX are left alone (requires --do-fix)
+    #[arg(long, value_name = "SUFFIX", value_parser = parse_rename_suffix, requires = "do_fix",
+        conflicts_with_all = ["replace_with", "trash", "reflink"])]
+    rename_suffix: Option<String>,
+    /// Same as --replace-with reflink, kept for compatibility
+    #[arg(long, hide = true, requires = "do_fix", conflicts_with = "trash")]
+    reflink: bool,
+    /// Write `rm` commands for the duplicates to a shell script at PATH, to review and
+    /// run later, instead of removing them (requires --do-fix)
+    #[arg(long, value_name = "PATH", requires = "do_fix",
+        conflicts_with_all = ["replace_with", "trash", "reflink", "rename_suffix", "dry_run", "prune_empty_dirs"])]
+    script: Option<std::path::PathBuf>,
+    /// Write the files kept in each group fixed to FILE, to replay them with
+    /// --apply-decisions (requires --do-fix)
+    #[arg(long, value_name = "FILE", requires = "do_fix")]
+    record_decisions: Option<std::path::PathBuf>,
+    /// Keep the files recorded by --record-decisions in FILE instead of choosing them,
+    /// skipping groups without a decision; --confirm still applies (requires --do-fix)
+    #[arg(long, value_name = "FILE", requires = "do_fix", conflicts_with_all = ["keep", "prefer"])]
+    apply_decisions: Option<std::path::PathBuf>,
+    /// Remove read-only files by making them writable first (requires --do-fix)
+    #[arg(long, requires = "do_fix")]
+    force: bool,
+    /// Remove directories that became empty after deleting duplicates (requires --do-fix)
+    #[arg(long, requires = "do_fix")]
+    prune_empty_dirs: bool,
+    /// When to ask for confirmation before changing files selected at the prompt (requires
+    /// --do-fix)
+    #[arg(long, value_enum, default_value_t = ConfirmMode::Batch, requires = "do_fix")]
+    confirm: ConfirmMode,
+    /// Only report files that also exist below this protected directory, which is never
+    /// modified; with --do-fix all other copies are removed (may be repeated)
+    #[arg(long, value_name = "PATH")]
+    reference: Vec<std::path::PathBuf>,
+    /// Like --reference, but take the protected files from FILE, a JSON, JSON Lines or CSV
+    /// listing written by --list-only, so that the reference data need not be present
+    #[arg(long, value_name = "FILE",
+        conflicts_with_all = ["reference", "from_stdin", "dirs", "by_name_size", "list_only",
+            "normalize_text", "partial_hash", "replace_with", "reflink"])]
+    reference_manifest: Option<std::path::PathBuf>,
+    /// Keep the copy below this directory when fixing; the first directory given takes
+    /// priority (may be repeated, requires --do-fix)
+    #[arg(long, value_name = "DIR", requires = "do_fix")]
+    prefer: Vec<std::path::PathBuf>,
+    /// Format of the duplicate report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Print redundant paths (all but one per group) separated by NUL bytes, for `xargs -0`
+    #[arg(long, conflicts_with_all = ["format", "do_fix"])]
+    print0: bool,
+    /// Write the duplicate report to this file instead of stdout
+    #[arg(short = 'o', long, value_name = "PATH")]
+    output: Option<std::path::PathBuf>,
+    /// Compare files byte by byte before treating matching hashes as duplicates
+    #[arg(long)]
+    verify: bool,
+    /// Also group text files that only differ in line endings or trailing whitespace;
+    /// such groups are labeled as normalized matches, and only their byte-identical copies
+    /// are fixed without --fix-normalized
+    #[arg(long, conflicts_with_all = ["verify", "dirs", "by_name_size", "list_only"])]
+    normalize_text: bool,
+    /// Also fix text files that only match as normalized text, which are otherwise only
+    /// reported (requires --normalize-text and --do-fix)
+    #[arg(long, requires_all = ["normalize_text", "do_fix"])]
+    fix_normalized: bool,
+    /// Only hash the first and last SIZE bytes and the size of larger files, e.g. 1MiB;
+    /// matches are reported as likely duplicates (--do-fix requires --verify)
+    #[arg(long, value_name = "SIZE", value_parser = parse_partial_hash,
+          conflicts_with_all = ["normalize_text", "dirs", "by_name_size", "list_only"])]
+    partial_hash: Option<u64>,
+    /// Number of worker threads used for hashing [default: available parallelism]
+    #[arg(short = 'j', long)]
+    jobs: Option<usize>,
+    /// Size of the read buffer used for hashing, e.g. 1MiB
+    #[arg(long, value_name = "SIZE", default_value = "64KiB", value_parser = parse_chunk_size)]
+    chunk_size: u64,
+    /// Hash files of 16 MiB and more through a memory map instead of reading them (Unix
+    /// only, also applies to --benchmark)
+    #[arg(long)]
+    mmap: bool,
+    /// Skip files smaller than this size (e.g. 10MB, 500KiB)
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Only fix groups of files of at least this size; smaller ones are still reported
+    /// (requires --do-fix)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "do_fix")]
+    fix_min_size: Option<u64>,
+    /// Skip files larger than this size (e.g. 10MB, 500KiB)
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Only consider files last modified at least this long ago (e.g. 30d, 12h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    older_than: Option<std::time::Duration>,
+    /// Follow symbolic links to files and directories
+    #[arg(long)]
+    follow_symlinks: bool,
+    /// Maximum number of symbolic links followed along a single path (requires
+    /// --follow-symlinks)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 40,
+        requires = "follow_symlinks"
+    )]
+    follow_to_depth: usize,
+    /// Treat hardlinks to the same file as a single file instead of duplicates
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    ignore_hardlinks: bool,
+    /// Ignore empty files instead of reporting them as duplicates of each other
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    skip_empty: bool,
+    /// List empty files separately from the duplicates; with --do-fix they are removed
+    #[arg(long)]
+    delete_empty_files: bool,
+    /// Do not descend into directories on other file systems, like `find -xdev`
+    #[arg(long)]
+    one_file_system: bool,
+    /// Report directories with identical content instead of single files; fixing
+    /// only removes the compared files and then the directories left empty
+    #[arg(long, conflicts_with_all = ["from_stdin", "reference", "replace_with", "reflink", "trash", "script", "same_dir_only", "match_ext"])]
+    dirs: bool,
+    /// Group files by name and size only, without reading them. The groups are
+    /// unverified candidates; fixing hashes them in full first
+    #[arg(long, conflicts_with_all = ["from_stdin", "reference", "dirs", "print0"])]
+    by_name_size: bool,
+    /// List the hash, size and path of every file, whether it has duplicates
+    /// or not, e.g. to build a manifest
+    #[arg(long, conflicts_with_all = ["do_fix", "from_stdin", "reference", "dirs", "by_name_size", "print0"])]
+    list_only: bool,
+    /// Only report duplicates that are in the same directory
+    #[arg(long)]
+    same_dir_only: bool,
+    /// Only report duplicates that have the same extension, ignoring case
+    #[arg(long)]
+    match_ext: bool,
+    /// Include hidden files and directories (dotfiles, or the hidden attribute on Windows)
+    #[arg(long)]
+    hidden: bool,
+    /// Retry reading a file up to N times after errors that may be transient
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    retries: usize,
+    /// Abort on the first unreadable file or directory instead of skipping it
+    #[arg(long)]
+    strict: bool,
+    /// Do not show scan progress on stderr
+    #[arg(long)]
+    no_progress: bool,
+    /// Hash algorithm used to compare file contents
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Sha256)]
+    hash: HashAlgorithm,
+    /// Skip files and directories matching this glob pattern, relative to the scan root
+    /// (may be repeated)
+    #[arg(long, value_name = "PATTERN", value_parser = parse_exclude)]
+    exclude: Vec<String>,
+    /// Do not skip the entries that are excluded by default: Thumbs.db, .DS_Store,
+    /// desktop.ini, .git and node_modules
+    #[arg(long)]
+    no_default_excludes: bool,
+    /// Do not read exclude patterns from .fdcignore files in the scanned directories
+    #[arg(long)]
+    no_ignore_files: bool,
+    /// Only consider files with this extension, case-insensitive (may be repeated)
+    #[arg(long = "ext", value_name = "EXT")]
+    extensions: Vec<String>,
+    /// Exit with code 2 if duplicates were found (ignored with --do-fix)
+    #[arg(long)]
+    fail_on_duplicates: bool,
+    /// Do not descend more than this many directory levels below each target (0 = top level only)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+    /// Only scan the files directly inside each target, same as --max-depth 0
+    #[arg(long, conflicts_with = "max_depth")]
+    no_recursive: bool,
+    /// Only print the duplicate groups, without banner, progress or summary
+    #[arg(short = 'q', long)]
+    quiet: bool,
+    /// Only report (and fix) the N groups that would free the most space
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+    /// Only report (and fix) groups of at least N identical files
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = parse_min_copies)]
+    min_copies: usize,
+    /// Only report (and fix) groups with at least one path matching this
+    /// regular expression
+    #[arg(long, value_name = "REGEX", value_parser = parse_filter)]
+    filter: Option<regex::Regex>,
+    /// Show paths relative to the scanned directory when scanning a single one
+    #[arg(long)]
+    resolve_relative: bool,
+    /// Report absolute paths with symbolic links and `.`/`..` resolved
+    #[arg(long, conflicts_with = "resolve_relative")]
+    absolute: bool,
+    /// Sort the duplicate groups by file extension and show a header for each
+    #[arg(long, conflicts_with = "dirs")]
+    group_by_ext: bool,
+    /// Show how many groups have 2, 3, 4, ... copies and how much space they free
+    #[arg(long)]
+    histogram: bool,
+    /// Print groups as soon as they are found, in no particular order, instead
+    /// of sorted after the scan (text and JSON Lines reports only)
+    #[arg(long, conflicts_with_all = ["do_fix", "reference", "reference_manifest", "top", "group_by_ext", "dirs", "by_name_size", "list_only"])]
+    stream: bool,
+    /// Show the modification time, age, size and directory of every listed file,
+    /// to help choosing the copy to keep
+    #[arg(long)]
+    details: bool,
+    /// Only scan a random FRACTION (e.g. 0.1) of the file sizes and extrapolate the
+    /// reclaimable space from it
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction,
+        conflicts_with_all = ["top", "from_stdin", "dirs"])]
+    sample: Option<f64>,
+    /// Show at most N paths per group in the text report (ignored with --do-fix)
+    #[arg(long, value_name = "N")]
+    max_list: Option<usize>,
+    /// Log scan decisions to stderr (-v: directories and skipped files, -vv: every hashed file)
+    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Print statistics about the scan to stderr when done
+    #[arg(long)]
+    stats: bool,
+    /// Print a summary of the results to stderr as a final line of JSON
+    #[arg(long)]
+    summary_json: bool,
+    /// Cache file hashes in this file and reuse them for unchanged files
+    #[arg(long, value_name = "PATH")]
+    cache: Option<std::path::PathBuf>,
+    /// Periodically save the hashes to this file while scanning and resume
+    /// from it after an interrupted scan; it is removed when the scan completes
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<std::path::PathBuf>,
+    /// Measure hashing throughput of FILE, or of an in-memory buffer, instead of scanning
+    #[arg(long, value_name = "FILE", num_args = 0..=1, conflicts_with_all = ["target_paths", "from_stdin", "do_fix"])]
+    benchmark: Option<Option<std::path::PathBuf>>,
+    /// Size of the in-memory buffer hashed by --benchmark [default: 64MiB]
+    #[arg(long, value_parser = parse_size, requires = "benchmark", conflicts_with_all = ["target_paths", "from_stdin"])]
+    size: Option<u64>,
+    /// When to use colors in the output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+}
+
+impl Cli {
+    /// The `--replace-with` mode, which `--reflink` sets as well.
+    fn replace_with(&self) -> Result<Option<ReplaceMode>, String> {
+        match (self.reflink, self.replace_with) {
+            (true, Some(ReplaceMode::Hardlink)) => {
+                Err("--reflink cannot be used with --replace-with hardlink".to_string())
+            }
+            (true, _) => Ok(Some(ReplaceMode::Reflink)),
+            (false, mode) => Ok(mode),
+        }
+    }
 }
 
 fn main() -> std::process::ExitCode {
     let cli = Cli::parse();
-    let target_dir = cli.target_path;
-    println!("Scanning directory {target_dir:?} for duplicates...");
-    match run(&target_dir, cli.do_fix) {
-        Ok(_) => std::process::ExitCode::SUCCESS,
+    env_logger::Builder::new()
+        .filter_level(match cli.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        })
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+    if cli.output.is_some()
+        && cli.do_fix
+        && cli.keep.is_none()
+        && cli.reference.is_empty()
+        && cli.reference_manifest.is_none()
+        && (cli.apply_decisions.is_none() || cli.confirm != ConfirmMode::Never)
+    {
+        // the interactive prompt needs the group listing on the terminal
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--output requires --keep when used with --do-fix",
+            )
+            .exit();
+    }
+    let replace_with = cli.replace_with().unwrap_or_else(|e| {
+        Cli::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, e)
+            .exit()
+    });
+    if cli.partial_hash.is_some() && cli.do_fix && !cli.verify {
+        // only a full comparison shows that the files are duplicates
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--partial-hash requires --verify when used with --do-fix",
+            )
+            .exit();
+    }
+    if cli.from_stdin
+        && cli.do_fix
+        && cli.keep.is_none()
+        && (cli.apply_decisions.is_none() || cli.confirm != ConfirmMode::Never)
+    {
+        // stdin holds the file list and cannot answer the prompt
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--from-stdin requires --keep when used with --do-fix",
+            )
+            .exit();
+    }
+    cli.color.apply(cli.output.is_some());
+    let chunk_size = clamp_chunk_size(cli.chunk_size);
+    if let Some(file) = &cli.benchmark {
+        let jobs = cli.jobs.unwrap_or(ScanOptions::default().jobs);
+        return match run_benchmark(
+            file.as_deref(),
+            cli.size.unwrap_or(DEFAULT_BENCHMARK_SIZE),
+            cli.hash,
+            jobs,
+            chunk_size,
+            cli.mmap,
+        ) {
+            Ok(()) => std::process::ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error while benchmarking: {e}");
+                std::process::ExitCode::FAILURE
+            }
+        };
+    }
+    let targets = if cli.from_stdin {
+        match read_path_list(&mut std::io::stdin()) {
+            Ok(files) => Targets::Files(files),
+            Err(e) => {
+                eprintln!("Error while reading the file list: {e}");
+                return std::process::ExitCode::FAILURE;
+            }
+        }
+    } else {
+        Targets::Dirs(cli.target_paths)
+    };
+    let dirs = match &targets {
+        Targets::Dirs(dirs) => dirs.as_slice(),
+        Targets::Files(_) => &[],
+    };
+    if let Err(e) = validate_dirs(dirs)
+        .and_then(|()| validate_dirs(&cli.reference))
+        .and_then(|()| validate_dirs(&cli.prefer))
+        .and_then(|()| validate_references(dirs, &cli.reference))
+    {
+        eprintln!("Error: {e}");
+        return std::process::ExitCode::from(EXIT_USAGE);
+    }
+    let format = if cli.print0 {
+        OutputFormat::Print0
+    } else {
+        cli.format
+    };
+    // the listing is meant to be processed further
+    if format == OutputFormat::Text && !cli.quiet && !cli.list_only {
+        match &targets {
+            Targets::Dirs(dirs) if dirs.len() == 1 => {
+                println!("Scanning directory {:?} for duplicates...", dirs[0])
+            }
+            Targets::Dirs(dirs) => println!("Scanning directories {dirs:?} for duplicates..."),
+            Targets::Files(files) => println!("Checking {} files for duplicates...", files.len()),
+        }
+    }
+    let mut options = ScanOptions {
+        min_size: cli.min_size,
+        max_size: cli.max_size,
+        verify: cli.verify,
+        follow_symlinks: cli.follow_symlinks,
+        max_symlinks: cli.follow_to_depth,
+        ignore_hardlinks: cli.ignore_hardlinks,
+        skip_empty: cli.skip_empty,
+        same_dir_only: cli.same_dir_only,
+        match_ext: cli.match_ext,
+        older_than: cli.older_than,
+        one_file_system: cli.one_file_system,
+        chunk_size,
+        collect_empty: cli.delete_empty_files,
+        retries: cli.retries,
+        sample: cli.sample,
+        mmap: cli.mmap,
+        hidden: cli.hidden,
+        strict: cli.strict,
+        hash: cli.hash,
+        exclude: cli.exclude,
+        default_excludes: !cli.no_default_excludes,
+        ignore_files: !cli.no_ignore_files,
+        extensions: cli.extensions,
+        max_depth: if cli.no_recursive {
+            Some(0)
+        } else {
+            cli.max_depth
+        },
+        cache: cli.cache,
+        checkpoint: cli.checkpoint,
+        min_copies: cli.min_copies,
+        normalize_text: cli.normalize_text,
+        partial_hash: cli.partial_hash,
+        progress: !cli.no_progress && !cli.quiet && std::io::stderr().is_terminal(),
+        ..ScanOptions::default()
+    };
+    if let Some(jobs) = cli.jobs {
+        options.jobs = jobs;
+    }
+    let script = match &cli.script {
+        Some(path) => match Script::create(path) {
+            Ok(script) => Some(std::sync::Arc::new(script)),
+            Err(e) => {
+                eprintln!("Error: unable to create script {path:?}: {e}");
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let record_decisions = match &cli.record_decisions {
+        Some(path) => match DecisionLog::create(path) {
+            Ok(log) => Some(std::sync::Arc::new(log)),
+            Err(e) => {
+                eprintln!("Error: unable to create decisions file {path:?}: {e}");
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let decisions = match &cli.apply_decisions {
+        Some(path) => match read_decisions(path) {
+            Ok(decisions) => Some(std::sync::Arc::new(decisions)),
+            Err(e) => {
+                eprintln!("Error: unable to read decisions file {path:?}: {e}");
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let manifest = match &cli.reference_manifest {
+        Some(path) => match read_manifest(path, options.hash) {
+            // so that the canonical paths found are still matched
+            Ok(manifest) if cli.absolute => Some(
+                manifest
+                    .into_iter()
+                    .map(|f| HashedFile {
+                        path: absolute_path(&f.path),
+                        ..f
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                eprintln!("Error: unable to read manifest {path:?}: {e}");
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let fix = cli.do_fix.then_some(FixOptions {
+        keep: cli.keep,
+        dry_run: cli.dry_run,
+        replace_with,
+        trash: cli.trash,
+        rename_suffix: cli.rename_suffix,
+        prune_empty_dirs: cli.prune_empty_dirs,
+        // only hand-made selections are confirmed, and only if files would change
+        confirm: if cli.keep.is_some()
+            || !cli.reference.is_empty()
+            || cli.reference_manifest.is_some()
+            || cli.dry_run
+            || script.is_some()
+        {
+            ConfirmMode::Never
+        } else {
+            cli.confirm
+        },
+        interactive: cli.keep.is_none()
+            && cli.reference.is_empty()
+            && cli.reference_manifest.is_none()
+            && decisions.is_none(),
+        force: cli.force,
+        min_size: cli.fix_min_size,
+        fix_normalized: cli.fix_normalized,
+        prefer: if cli.absolute {
+            cli.prefer.iter().map(|p| absolute_path(p)).collect()
+        } else {
+            cli.prefer
+        },
+        script,
+        record_decisions,
+        decisions,
+    });
+    let report = ReportOptions {
+        format,
+        quiet: cli.quiet,
+        reference: if cli.absolute {
+            // so that the canonical paths found are still matched
+            cli.reference.iter().map(|r| absolute_path(r)).collect()
+        } else {
+            cli.reference
+        },
+        manifest_paths: manifest.iter().flatten().map(|f| f.path.clone()).collect(),
+        manifest,
+        top: cli.top,
+        filter: cli.filter,
+        max_list: cli.max_list,
+        dirs: cli.dirs,
+        by_name_size: cli.by_name_size,
+        list_only: cli.list_only,
+        absolute: cli.absolute,
+        group_by_ext: cli.group_by_ext,
+        histogram: cli.histogram,
+        stream: cli.stream,
+        details: cli.details,
+        normalized: std::collections::HashSet::new(),
+        byte_identical: std::collections::HashMap::new(),
+        likely: std::collections::HashSet::new(),
+        sample: cli.sample,
+        relative_to: match &targets {
+            // with several roots a relative path would not tell which one it is in
+            Targets::Dirs(dirs) if cli.resolve_relative && dirs.len() == 1 => Some(dirs[0].clone()),
+            _ => None,
+        },
+    };
+    let start = std::time::Instant::now();
+    let result = run(
+        &targets,
+        &options,
+        &report,
+        fix.as_ref(),
+        cli.output.as_deref(),
+    );
+    let elapsed = start.elapsed();
+    match result {
+        Ok(summary) => {
+            if cli.stats {
+                print_stats(&summary, elapsed);
+            }
+            if cli.summary_json {
+                print_summary_json(&summary);
+            }
+            if summary.interrupted {
+                std::process::ExitCode::from(EXIT_INTERRUPTED)
+            } else if !summary.stats.errors.is_empty() {
+                std::process::ExitCode::from(EXIT_SCAN_ERRORS)
+            } else if summary.duplicates_found && cli.fail_on_duplicates {
+                std::process::ExitCode::from(EXIT_DUPLICATES_FOUND)
+            } else {
+                std::process::ExitCode::SUCCESS
+            }
+        }
         Err(e) => {
             std::eprintln!("Error while scanning: {e}");
             std::process::ExitCode::FAILURE
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflink_is_an_alias_of_replace_with() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["duplicate_checker", "-f", "."].iter().chain(args))
+                .map_err(|e| e.to_string())
+                .and_then(|cli| cli.replace_with())
+        };
+        assert_eq!(parse(&["--reflink"]), Ok(Some(ReplaceMode::Reflink)));
+        assert_eq!(
+            parse(&["--reflink", "--replace-with", "reflink"]),
+            Ok(Some(ReplaceMode::Reflink))
+        );
+        assert!(parse(&["--reflink", "--replace-with", "hardlink"]).is_err());
+        assert!(parse(&["--reflink", "--trash"]).is_err());
+        assert_eq!(parse(&[]), Ok(None));
+    }
+
+    #[test]
+    fn renamed_files_are_not_renamed_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tagged, plain) = (dir.path().join("a.dup"), dir.path().join("b"));
+        std::fs::write(&tagged, "x").unwrap();
+        std::fs::write(&plain, "x").unwrap();
+        let fix = FixOptions {
+            rename_suffix: Some(".dup".to_string()),
+            ..FixOptions::default()
+        };
+        let report = ReportOptions::default();
+        let mut summary = FixSummary::default();
+        let mut out = Vec::new();
+        assert!(!remove_file(&tagged, &report, &fix, &mut summary, &mut out).unwrap());
+        assert!(remove_file(&plain, &report, &fix, &mut summary, &mut out).unwrap());
+        assert!(tagged.exists());
+        assert!(dir.path().join("b.dup").exists());
+        assert!(!dir.path().join("a.dup.dup").exists());
+    }
+
+    #[test]
+    fn targets_inside_the_reference_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = [dir.path().join("archive")];
+        let import = [archive[0].join("import")];
+        let other = [dir.path().join("other")];
+        std::fs::create_dir_all(&import[0]).unwrap();
+        std::fs::create_dir(&other[0]).unwrap();
+        let error = validate_references(&import, &archive).unwrap_err();
+        assert!(error.contains("inside the reference"), "{error}");
+        assert!(validate_references(&archive, &archive).is_err());
+        // a reference inside the target is told apart by its prefix
+        assert!(validate_references(&[dir.path().into()], &archive).is_ok());
+        assert!(validate_references(&other, &archive).is_ok());
+    }
+
+    #[test]
+    fn dir_copies_keep_files_that_were_not_compared() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kept, copy, same) = (
+            dir.path().join("x"),
+            dir.path().join("y"),
+            dir.path().join("z"),
+        );
+        for d in [&kept, &copy, &same] {
+            std::fs::create_dir_all(d.join("sub")).unwrap();
+            std::fs::write(d.join("a.txt"), "a").unwrap();
+            std::fs::write(d.join("sub/b.txt"), "b").unwrap();
+        }
+        // hidden and excluded files are left out of the comparison
+        std::fs::write(copy.join(".secret"), "s").unwrap();
+        std::fs::create_dir(copy.join("node_modules")).unwrap();
+        std::fs::write(copy.join("node_modules/dep.js"), "d").unwrap();
+        let report = ReportOptions {
+            dirs: true,
+            ..Default::default()
+        };
+        let dry_run = FixOptions {
+            dry_run: true,
+            ..FixOptions::default()
+        };
+        let mut summary = FixSummary::default();
+        let mut out = Vec::new();
+        remove_dir_copy(&copy, &kept, &report, &dry_run, &mut summary, &mut out).unwrap();
+        assert!(copy.join("a.txt").exists() && copy.join("sub/b.txt").exists());
+        assert_eq!((summary.files, summary.bytes), (2, 2));
+        let fix = FixOptions::default();
+        let mut summary = FixSummary::default();
+        remove_dir_copy(&copy, &kept, &report, &fix, &mut summary, &mut out).unwrap();
+        remove_dir_copy(&same, &kept, &report, &fix, &mut summary, &mut out).unwrap();
+        assert!(!copy.join("a.txt").exists());
+        assert!(!copy.join("sub").exists());
+        assert!(copy.join(".secret").exists());
+        assert!(copy.join("node_modules/dep.js").exists());
+        assert!(!same.exists());
+        assert!(kept.join("a.txt").exists() && kept.join("sub/b.txt").exists());
+        assert_eq!(summary.files, 4);
+    }
+
+    #[test]
+    fn shell_quote_keeps_every_byte() {
+        let quote = |path: &str| String::from_utf8(shell_quote(path.as_ref())).unwrap();
+        assert_eq!(quote("plain.txt"), "'plain.txt'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("two\nlines $HOME"), "'two\nlines $HOME'");
+        assert_eq!(quote("-rf"), "'-rf'");
+    }
+
+    #[test]
+    fn script_removes_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fix.sh");
+        let script = Script::create(&path).unwrap();
+        script.remove("relative/file".as_ref()).unwrap();
+        script.flush().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let removal = content.lines().last().unwrap();
+        let quoted = shell_quote(&absolute_path("relative/file".as_ref()));
+        assert_eq!(
+            removal.as_bytes(),
+            [b"rm -f -- ".as_slice(), &quoted].concat()
+        );
+        assert!(std::path::Path::new(&removal["rm -f -- '".len()..]).is_absolute());
+    }
+
+    /// A group of `size` byte files at `paths`, with a made-up hash.
+    fn group(size: u64, paths: &[&str]) -> (Hash, FileInfo) {
+        let info = FileInfo {
+            paths: paths.iter().map(std::path::PathBuf::from).collect(),
+            size,
+        };
+        (
+            Hash::new(HashAlgorithm::default(), &[paths.len() as u8]),
+            info,
+        )
+    }
+
+    fn summary(duplicates: &[(Hash, FileInfo)], report: &ReportOptions) -> String {
+        let mut out = Vec::new();
+        write_summary(duplicates, report, None, &FixSummary::default(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reference_copies_are_not_reclaimable() {
+        let duplicates = [
+            group(5, &["archive/a", "import/a"]),
+            group(3, &["archive/b", "archive/c", "import/b", "import/c"]),
+        ];
+        assert_eq!(
+            summary(&duplicates, &ReportOptions::default()),
+            "Found 2 duplicate groups, 4 redundant files, 14 bytes reclaimable\n"
+        );
+        let report = ReportOptions {
+            reference: vec!["archive".into()],
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            summary(&duplicates, &report),
+            "Found 2 duplicate groups, 3 redundant files, 11 bytes reclaimable\n"
+        );
+    }
+
+    #[test]
+    fn only_byte_identical_normalized_copies_are_reclaimable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(path("a.txt"), "hello\n").unwrap();
+        std::fs::write(path("b.txt"), "hello\n").unwrap();
+        std::fs::write(path("c.txt"), "hello\r\n").unwrap();
+        let (k, v) = group(6, &[&path("c.txt"), &path("b.txt"), &path("a.txt")]);
+        let groups = byte_identical_groups(&v);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[0].paths,
+            [path("a.txt"), path("b.txt")].map(std::path::PathBuf::from)
+        );
+        assert_eq!(groups[1].size, 7);
+        let report = ReportOptions {
+            normalized: [k].into(),
+            byte_identical: [(k, groups)].into(),
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            summary(&[(k, v)], &report),
+            "Found 1 duplicate groups, 1 redundant files, 6 bytes reclaimable\n"
+        );
+    }
+
+    #[test]
+    fn manifest_entries_are_not_reclaimable() {
+        let duplicates = [group(5, &["offline/a", "import/a", "import/b"])];
+        let report = ReportOptions {
+            manifest: Some(Vec::new()),
+            manifest_paths: ["offline/a".into()].into(),
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            summary(&duplicates, &report),
+            "Found 1 duplicate groups, 2 redundant files, 10 bytes reclaimable\n"
+        );
+    }
+
+    #[test]
+    fn csv_records_undo_the_quoting() {
+        let fields = ["plain", "with,comma", "with \"quotes\"", "two\nlines", ""];
+        let line: Vec<_> = fields.iter().map(|f| csv_field(f)).collect();
+        let data = format!("{}\r\nlast,row\n", line.join(","));
+        assert_eq!(
+            csv_records(&data),
+            Ok(vec![
+                fields.iter().map(|f| f.to_string()).collect(),
+                vec!["last".to_string(), "row".to_string()],
+            ])
+        );
+        assert_eq!(csv_records("a,b"), Ok(vec![vec!["a".into(), "b".into()]]));
+        assert!(csv_records("a,\"open\n").is_err());
+    }
+
+    /// Writes `content` to a manifest file and reads it back.
+    fn manifest(content: &str) -> Result<Vec<HashedFile>, std::io::Error> {
+        manifest_of(content, HashAlgorithm::default())
+    }
+
+    fn manifest_of(
+        content: &str,
+        algorithm: HashAlgorithm,
+    ) -> Result<Vec<HashedFile>, std::io::Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest");
+        std::fs::write(&path, content).unwrap();
+        read_manifest(&path, algorithm)
+    }
+
+    #[test]
+    fn manifests_are_read_in_every_listing_format() {
+        let hash = "ab".repeat(32);
+        let csv = format!("hash,size,path\n{hash},5,\"dir/a,\"\"b\"\"\"\n");
+        let jsonl = format!("{{\"hash\":\"{hash}\",\"size\":5,\"path\":\"dir/a,\\\"b\\\"\"}}\n");
+        let json = format!("[\n{}]", jsonl.trim_end());
+        for content in [csv, jsonl, json] {
+            let files = manifest(&content).unwrap();
+            assert_eq!(files.len(), 1, "{content}");
+            assert_eq!(files[0].path, std::path::Path::new("dir/a,\"b\""));
+            assert_eq!(files[0].size, 5);
+            assert_eq!(files[0].hash.to_string(), hash);
+        }
+        assert!(manifest("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_manifests_are_rejected() {
+        let hash = "ab".repeat(32);
+        for content in [
+            format!("path,size,hash\n{hash},5,a\n"),
+            format!("hash,size,path\n{hash},5\n"),
+            format!("hash,size,path\n{hash},5,a,b\n"),
+            format!("hash,size,path\n{hash},five,a\n"),
+            "hash,size,path\nxyz,5,a\n".to_string(),
+            format!("hash,size,path\n{hash},5,\"a\n"),
+            "{\"hash\":\"ab\"}\n".to_string(),
+            "[{\"hash\":".to_string(),
+        ] {
+            assert!(manifest(&content).is_err(), "{content}");
+        }
+    }
+
+    #[test]
+    fn manifests_of_another_algorithm_are_rejected() {
+        let sha256 = format!("hash,size,path\n{},5,a\n", "ab".repeat(32));
+        let error = manifest_of(&sha256, HashAlgorithm::Xxh3).unwrap_err();
+        assert!(error.to_string().contains("not a xxh3 digest"), "{error}");
+        let xxh3 = format!("hash,size,path\n{},5,a\n", "ab".repeat(16));
+        assert!(manifest_of(&xxh3, HashAlgorithm::Sha256).is_err());
+        assert!(manifest_of(&xxh3, HashAlgorithm::Xxh3).is_ok());
+    }
+
+    #[test]
+    fn sizes_accept_decimal_and_binary_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10MB"), Ok(10_000_000));
+        assert_eq!(parse_size(" 500 KiB "), Ok(500 * 1024));
+        assert_eq!(parse_size("1.5k"), Ok(1500));
+        assert_eq!(parse_size("2tib"), Ok(2 << 40));
+    }
+
+    #[test]
+    fn malformed_sizes_are_rejected() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("1.2.3").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("99999999tb").is_err());
+    }
+
+    #[test]
+    fn durations_need_a_unit() {
+        let hours = |h: u64| std::time::Duration::from_secs(h * 60 * 60);
+        assert_eq!(parse_duration("12h"), Ok(hours(12)));
+        assert_eq!(parse_duration("30d"), Ok(hours(30 * 24)));
+        assert_eq!(
+            parse_duration("1.5min"),
+            Ok(std::time::Duration::from_secs(90))
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+        assert!(parse_duration("99999999999999999999999w").is_err());
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        let at = |secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        assert_eq!(format_timestamp(at(0)), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(at(951_825_784)), "2000-02-29 12:03:04 UTC");
+        assert_eq!(
+            format_timestamp(std::time::UNIX_EPOCH - std::time::Duration::from_secs(1)),
+            "1969-12-31 23:59:59 UTC"
+        );
+    }
+
+    #[test]
+    fn ages_use_the_largest_unit() {
+        let age = std::time::Duration::from_secs;
+        assert_eq!(format_age(age(59)), "just now");
+        assert_eq!(format_age(age(60)), "1 minute ago");
+        assert_eq!(format_age(age(3 * 86400 + 5)), "3 days ago");
+        assert_eq!(format_age(age(2 * 31_536_000)), "2 years ago");
+    }
+
+    #[test]
+    fn decisions_apply_from_another_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.jsonl");
+        let (k, _) = group(2, &["a", "b"]);
+        let log = DecisionLog::create(&path).unwrap();
+        log.record(&k, vec!["Cargo.toml".into()]).unwrap();
+        let decisions = read_decisions(&path).unwrap();
+        let kept = &decisions[&k.to_string()];
+        assert_eq!(kept, &[std::fs::canonicalize("Cargo.toml").unwrap()]);
+        // the same files as named relative to another working directory
+        let v = FileInfo {
+            paths: vec!["src/../Cargo.toml".into(), "src/main.rs".into()],
+            size: 2,
+        };
+        let fix = FixOptions {
+            decisions: Some(decisions.into()),
+            ..FixOptions::default()
+        };
+        let choice =
+            choose_files_to_keep(&k, &v, &ReportOptions::default(), &fix, &mut Vec::new()).unwrap();
+        assert!(matches!(choice, Choice::Keep(indices) if indices == [0]));
+    }
+
+    #[test]
+    fn later_decisions_replace_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.jsonl");
+        std::fs::write(
+            &path,
+            "{\"hash\":\"ab\",\"keep\":[\"a\"]}\n\n{\"hash\":\"ab\",\"keep\":[\"b\",\"c\"]}\n",
+        )
+        .unwrap();
+        let decisions = read_decisions(&path).unwrap();
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions["ab"], [std::path::Path::new("b"), "c".as_ref()]);
+        std::fs::write(&path, "{\"hash\":\"ab\",\"keep\":[\"a\"]}\nnot json\n").unwrap();
+        let error = read_decisions(&path).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"), "{error}");
+    }
+}
@@ -0,0 +1,163 @@
+//! Throttled progress display on stderr.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Minimum time between two refreshes of the progress line.
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Counts scanned files and bytes and hashed bytes and periodically prints
+/// them.
+///
+/// Once the walk is done, the bytes queued for hashing are known and the
+/// display adds the share hashed so far and the estimated time remaining.
+///
+/// The counters are always maintained, printing only happens when the display
+/// is enabled. Updates may come from several hashing threads at once.
+#[derive(Default)]
+pub(crate) struct Progress {
+    enabled: bool,
+    files_scanned: AtomicU64,
+    bytes_scanned: AtomicU64,
+    bytes_hashed: AtomicU64,
+    bytes_queued: AtomicU64,
+    /// Time the walk finished, with the bytes hashed until then.
+    walk_done: std::sync::OnceLock<(std::time::Instant, u64)>,
+    last_refresh: std::sync::Mutex<Option<std::time::Instant>>,
+}
+
+impl Progress {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            files_scanned: AtomicU64::new(0),
+            bytes_scanned: AtomicU64::new(0),
+            bytes_hashed: AtomicU64::new(0),
+            bytes_queued: AtomicU64::new(0),
+            walk_done: std::sync::OnceLock::new(),
+            last_refresh: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn file_scanned(&self, size: u64) {
+        self.files_scanned.fetch_add(1, Ordering::Relaxed);
+        self.bytes_scanned.fetch_add(size, Ordering::Relaxed);
+        self.refresh();
+    }
+
+    pub(crate) fn bytes_hashed(&self, bytes: u64) {
+        self.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+        self.refresh();
+    }
+
+    /// Counts bytes that a dispatched job is going to hash.
+    pub(crate) fn bytes_queued(&self, bytes: u64) {
+        self.bytes_queued.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Takes back queued bytes that need no reading after all, like those of
+    /// a file whose hash is found in the cache, so that they do not count as
+    /// remaining work.
+    pub(crate) fn bytes_skipped(&self, bytes: u64) {
+        self.bytes_queued.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn walk_done(&self) {
+        let _ = self
+            .walk_done
+            .set((std::time::Instant::now(), self.total_bytes_hashed()));
+    }
+
+    pub(crate) fn total_files_scanned(&self) -> u64 {
+        self.files_scanned.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn total_bytes_hashed(&self) -> u64 {
+        self.bytes_hashed.load(Ordering::Relaxed)
+    }
+
+    fn refresh(&self) {
+        if !self.enabled {
+            return;
+        }
+        // skip the update if another thread is currently printing
+        let Ok(mut last_refresh) = self.last_refresh.try_lock() else {
+            return;
+        };
+        if last_refresh.is_some_and(|t| t.elapsed() < REFRESH_INTERVAL) {
+            return;
+        }
+        *last_refresh = Some(std::time::Instant::now());
+        let hashed = self.total_bytes_hashed();
+        eprint!(
+            "\r\x1b[KScanned {} files / {}, ",
+            format_count(self.total_files_scanned()),
+            crate::format_bytes(self.bytes_scanned.load(Ordering::Relaxed)),
+        );
+        match self.walk_done.get() {
+            Some((since, hashed_before)) => {
+                // queued bytes can be read more than once by retries
+                let queued = self.bytes_queued.load(Ordering::Relaxed).max(hashed);
+                eprint!(
+                    "{} of {} hashed ({}%)",
+                    crate::format_bytes(hashed),
+                    crate::format_bytes(queued),
+                    (hashed as f64 * 100.0 / queued.max(1) as f64) as u64
+                );
+                let elapsed = since.elapsed().as_secs_f64();
+                let rate = (hashed - hashed_before) as f64 / elapsed;
+                if elapsed >= 1.0 && rate > 0.0 {
+                    let remaining = (queued - hashed) as f64 / rate;
+                    eprint!(", ETA {}", format_eta(remaining as u64));
+                }
+            }
+            None => eprint!("{} hashed", crate::format_bytes(hashed)),
+        }
+        let _ = std::io::stderr().flush();
+    }
+
+    /// Clears the progress line for output written during the scan. The line
+    /// is printed again on the next refresh.
+    pub(crate) fn clear(&self) {
+        if !self.enabled {
+            return;
+        }
+        let mut last_refresh = self
+            .last_refresh
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        *last_refresh = Some(std::time::Instant::now());
+        eprint!("\r\x1b[K");
+        let _ = std::io::stderr().flush();
+    }
+
+    /// Clears the progress line so that regular output starts on a clean line.
+    pub(crate) fn finish(&self) {
+        if self.enabled {
+            eprint!("\r\x1b[K");
+            let _ = std::io::stderr().flush();
+        }
+    }
+}
+
+/// Formats a number of seconds like `1h 05m`, `3m 20s` or `45s`.
+fn format_eta(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Formats `n` with thousands separators, e.g. `12,340`.
+fn format_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
@@ -0,0 +1,134 @@
+//! Hashing of text files in a normalized form, for
+//! [`crate::ScanOptions::normalize_text`].
+
+use crate::{ContentHasher, Hash, HashFunction};
+use std::io::Read;
+
+/// Number of bytes at the start of a file that decide whether it is text.
+const TEXT_DETECTION_LEN: usize = 8000;
+
+/// Whether `start`, the beginning of a file, looks like text. Like git, a file
+/// is taken as binary if it contains a NUL byte.
+fn is_text(start: &[u8]) -> bool {
+    !start.contains(&0)
+}
+
+/// Whether the file at `path` looks like text, judging from its beginning.
+pub(crate) fn is_text_file(path: &std::path::Path) -> Result<bool, std::io::Error> {
+    let mut start = Vec::with_capacity(TEXT_DETECTION_LEN);
+    std::fs::File::open(crate::long_path(path))?
+        .take(TEXT_DETECTION_LEN as u64)
+        .read_to_end(&mut start)?;
+    Ok(is_text(&start))
+}
+
+/// Wraps a hash function so that text is hashed with unified line endings and
+/// without trailing whitespace on each line and at the end of the file.
+/// Binary files are hashed as they are.
+#[derive(Debug)]
+pub(crate) struct NormalizedText<'a>(pub(crate) &'a dyn HashFunction);
+
+impl HashFunction for NormalizedText<'_> {
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        Box::new(NormalizingHasher {
+            inner: self.0.hasher(),
+            state: State::Detecting(Vec::new()),
+        })
+    }
+}
+
+enum State {
+    /// Collecting the start of the file until it is known to be text.
+    Detecting(Vec<u8>),
+    Binary,
+    Text {
+        /// Line breaks and whitespace not written yet, as they are dropped
+        /// if nothing but whitespace follows.
+        line_breaks: usize,
+        whitespace: Vec<u8>,
+        /// Whether the last byte was a CR, so that a following LF belongs
+        /// to the same line break.
+        after_cr: bool,
+    },
+}
+
+struct NormalizingHasher {
+    inner: Box<dyn ContentHasher>,
+    state: State,
+}
+
+impl NormalizingHasher {
+    /// Decides on text or binary given the bytes collected so far.
+    fn detect(&mut self) {
+        let State::Detecting(start) = &mut self.state else {
+            return;
+        };
+        let start = std::mem::take(start);
+        if is_text(&start) {
+            self.state = State::Text {
+                line_breaks: 0,
+                whitespace: Vec::new(),
+                after_cr: false,
+            };
+            self.normalize(&start);
+        } else {
+            self.state = State::Binary;
+            self.inner.update(&start);
+        }
+    }
+
+    fn normalize(&mut self, data: &[u8]) {
+        let State::Text {
+            line_breaks,
+            whitespace,
+            after_cr,
+        } = &mut self.state
+        else {
+            return;
+        };
+        let mut normalized = Vec::with_capacity(data.len());
+        for &b in data {
+            if std::mem::take(after_cr) && b == b'\n' {
+                continue;
+            }
+            match b {
+                b'\r' | b'\n' => {
+                    *after_cr = b == b'\r';
+                    *line_breaks += 1;
+                    whitespace.clear();
+                }
+                b' ' | b'\t' => whitespace.push(b),
+                _ => {
+                    normalized.extend(std::iter::repeat_n(b'\n', *line_breaks));
+                    *line_breaks = 0;
+                    normalized.append(whitespace);
+                    normalized.push(b);
+                }
+            }
+        }
+        self.inner.update(&normalized);
+    }
+}
+
+impl ContentHasher for NormalizingHasher {
+    fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            State::Detecting(start) => {
+                let missing = TEXT_DETECTION_LEN - start.len();
+                let (head, rest) = data.split_at(missing.min(data.len()));
+                start.extend_from_slice(head);
+                if start.len() == TEXT_DETECTION_LEN {
+                    self.detect();
+                    self.update(rest);
+                }
+            }
+            State::Binary => self.inner.update(data),
+            State::Text { .. } => self.normalize(data),
+        }
+    }
+
+    fn finalize(mut self: Box<Self>) -> Hash {
+        self.detect();
+        self.inner.finalize()
+    }
+}
@@ -0,0 +1,518 @@
+//! Duplicate detection on small directory trees built in a temporary directory.
+
+use duplicate_checker::{
+    find_duplicate_dirs, find_duplicates, find_duplicates_among, find_duplicates_of_manifest,
+    find_duplicates_of_reference, find_duplicates_with, find_name_size_candidates, hash_all_files,
+    ContentHasher, Hash, HashAlgorithm, HashFunction, ScanOptions,
+};
+
+/// Creates the files listed as `(relative path, content)` below `root`.
+fn create_files(root: &std::path::Path, files: &[(&str, &str)]) {
+    for (path, content) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+}
+
+/// Scans `root` and returns the size and the relative paths of each group.
+fn groups(root: &std::path::Path, options: &ScanOptions) -> Vec<(u64, Vec<String>)> {
+    let (duplicates, stats) = find_duplicates(root, options).unwrap();
+    assert!(stats.errors.is_empty(), "{:?}", stats.errors);
+    relative_groups(root, &duplicates)
+}
+
+fn relative_groups(
+    root: &std::path::Path,
+    duplicates: &[(duplicate_checker::Hash, duplicate_checker::FileInfo)],
+) -> Vec<(u64, Vec<String>)> {
+    duplicates
+        .iter()
+        .map(|(_, info)| {
+            let paths = info
+                .paths
+                .iter()
+                .map(|p| {
+                    let p = p.strip_prefix(root).unwrap();
+                    p.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+            (info.size, paths)
+        })
+        .collect()
+}
+
+fn tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(
+        dir.path(),
+        &[
+            ("a.txt", "hello"),
+            ("sub/b.txt", "hello"),
+            ("sub/deeper/c.log", "hello"),
+            ("big1.bin", "0123456789"),
+            ("sub/big2.bin", "0123456789"),
+            ("unique.txt", "world"),
+            ("empty1", ""),
+            ("sub/empty2", ""),
+        ],
+    );
+    dir
+}
+
+#[test]
+fn groups_are_sorted_by_size_and_path() {
+    let dir = tree();
+    assert_eq!(
+        groups(dir.path(), &ScanOptions::default()),
+        [
+            (10, vec!["big1.bin".into(), "sub/big2.bin".into()]),
+            (
+                5,
+                vec![
+                    "a.txt".into(),
+                    "sub/b.txt".into(),
+                    "sub/deeper/c.log".into()
+                ]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn empty_files_are_only_grouped_on_request() {
+    let dir = tree();
+    let options = ScanOptions {
+        skip_empty: false,
+        ..ScanOptions::default()
+    };
+    let found = groups(dir.path(), &options);
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[2], (0, vec!["empty1".into(), "sub/empty2".into()]));
+}
+
+#[test]
+fn size_range_filters_files() {
+    let dir = tree();
+    let options = ScanOptions {
+        min_size: Some(6),
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        [(10, vec!["big1.bin".into(), "sub/big2.bin".into()])]
+    );
+    let options = ScanOptions {
+        max_size: Some(6),
+        ..ScanOptions::default()
+    };
+    assert_eq!(groups(dir.path(), &options)[0].0, 5);
+}
+
+#[test]
+fn exclude_and_extension_filters() {
+    let dir = tree();
+    let options = ScanOptions {
+        exclude: vec!["deeper".into()],
+        extensions: vec!["TXT".into()],
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        [(5, vec!["a.txt".into(), "sub/b.txt".into()])]
+    );
+}
+
+#[test]
+fn max_depth_limits_the_walk() {
+    let dir = tree();
+    let options = ScanOptions {
+        max_depth: Some(1),
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        [
+            (10, vec!["big1.bin".into(), "sub/big2.bin".into()]),
+            (5, vec!["a.txt".into(), "sub/b.txt".into()]),
+        ]
+    );
+}
+
+#[test]
+fn same_dir_only_splits_groups() {
+    let dir = tree();
+    create_files(dir.path(), &[("sub/b2.txt", "hello")]);
+    let options = ScanOptions {
+        same_dir_only: true,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        [(5, vec!["sub/b.txt".into(), "sub/b2.txt".into()])]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn hidden_files_are_skipped_by_default() {
+    let dir = tree();
+    create_files(dir.path(), &[(".hidden/copy.txt", "world")]);
+    assert_eq!(groups(dir.path(), &ScanOptions::default()).len(), 2);
+    let options = ScanOptions {
+        hidden: true,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options)[2],
+        (5, vec![".hidden/copy.txt".into(), "unique.txt".into()])
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn broken_symlinks_are_reported_as_errors() {
+    let dir = tree();
+    let link = dir.path().join("dangling");
+    std::os::unix::fs::symlink(dir.path().join("missing"), &link).unwrap();
+    let options = ScanOptions {
+        follow_symlinks: true,
+        ..ScanOptions::default()
+    };
+    let (duplicates, stats) = find_duplicates(dir.path(), &options).unwrap();
+    assert_eq!(duplicates.len(), 2);
+    assert_eq!(stats.errors.len(), 1);
+    assert_eq!(stats.errors[0].path, link);
+    assert!(stats.errors[0].message.starts_with("broken symbolic link"));
+}
+
+#[test]
+fn verify_keeps_identical_groups() {
+    let dir = tree();
+    let options = ScanOptions {
+        verify: true,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        groups(dir.path(), &ScanOptions::default())
+    );
+}
+
+#[test]
+fn reference_groups_need_a_copy_on_both_sides() {
+    let dir = tree();
+    let reference = tempfile::tempdir().unwrap();
+    create_files(reference.path(), &[("ref.bin", "0123456789")]);
+    let (duplicates, _) =
+        find_duplicates_of_reference(&[reference.path()], &[dir.path()], &ScanOptions::default())
+            .unwrap();
+    assert_eq!(duplicates.len(), 1);
+    let paths = &duplicates[0].1.paths;
+    assert_eq!(paths.len(), 3);
+    assert!(paths.contains(&reference.path().join("ref.bin")));
+}
+
+#[test]
+fn file_lists_are_compared_among_themselves() {
+    let dir = tree();
+    let files = [
+        dir.path().join("a.txt"),
+        dir.path().join("sub/deeper/c.log"),
+        dir.path().join("big1.bin"),
+    ];
+    let (duplicates, stats) = find_duplicates_among(&files, &ScanOptions::default()).unwrap();
+    assert!(stats.errors.is_empty());
+    assert_eq!(
+        relative_groups(dir.path(), &duplicates),
+        [(5, vec!["a.txt".into(), "sub/deeper/c.log".into()])]
+    );
+}
+
+#[test]
+fn identical_directories_are_reported_once() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(
+        dir.path(),
+        &[
+            ("album1/a.jpg", "photo a"),
+            ("album1/raw/b.raw", "photo b"),
+            ("backup/album2/a.jpg", "photo a"),
+            ("backup/album2/raw/b.raw", "photo b"),
+            ("partial/a.jpg", "photo a"),
+            ("partial/c.jpg", "photo c"),
+        ],
+    );
+    let (duplicates, _) = find_duplicate_dirs(&[dir.path()], &ScanOptions::default()).unwrap();
+    assert_eq!(
+        relative_groups(dir.path(), &duplicates),
+        [(14, vec!["album1".into(), "backup/album2".into()])]
+    );
+}
+
+#[test]
+fn name_size_candidates_ignore_content() {
+    let dir = tree();
+    create_files(
+        dir.path(),
+        &[("other/a.txt", "jello"), ("other/big1.bin", "0")],
+    );
+    let (candidates, stats) =
+        find_name_size_candidates(&[dir.path()], &ScanOptions::default()).unwrap();
+    assert_eq!(stats.bytes_hashed, 0);
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(
+        candidates[0].paths,
+        [dir.path().join("a.txt"), dir.path().join("other/a.txt")]
+    );
+}
+
+#[test]
+fn default_excludes_can_be_turned_off() {
+    let dir = tree();
+    create_files(dir.path(), &[("node_modules/copy.txt", "world")]);
+    assert_eq!(groups(dir.path(), &ScanOptions::default()).len(), 2);
+    let options = ScanOptions {
+        default_excludes: false,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options)[2],
+        (5, vec!["node_modules/copy.txt".into(), "unique.txt".into()])
+    );
+}
+
+#[test]
+fn ignore_files_apply_to_their_subtree() {
+    let dir = tree();
+    create_files(
+        dir.path(),
+        &[
+            ("sub/.fdcignore", "# copies\n*.log\n\nbig2.bin\n"),
+            ("other/c.log", "hello"),
+        ],
+    );
+    assert_eq!(
+        groups(dir.path(), &ScanOptions::default()),
+        [(
+            5,
+            vec!["a.txt".into(), "other/c.log".into(), "sub/b.txt".into()]
+        )]
+    );
+    let options = ScanOptions {
+        ignore_files: false,
+        ..ScanOptions::default()
+    };
+    assert_eq!(groups(dir.path(), &options).len(), 2);
+}
+
+#[test]
+fn match_ext_splits_groups_by_extension() {
+    let dir = tree();
+    create_files(dir.path(), &[("sub/d.LOG", "hello")]);
+    let options = ScanOptions {
+        match_ext: true,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options),
+        [
+            (10, vec!["big1.bin".into(), "sub/big2.bin".into()]),
+            (5, vec!["a.txt".into(), "sub/b.txt".into()]),
+            (5, vec!["sub/d.LOG".into(), "sub/deeper/c.log".into()]),
+        ]
+    );
+}
+
+#[test]
+fn min_copies_drops_smaller_groups() {
+    let dir = tree();
+    let options = ScanOptions {
+        min_copies: 3,
+        ..ScanOptions::default()
+    };
+    let found = groups(dir.path(), &options);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, 5);
+}
+
+#[test]
+fn listing_contains_unique_files() {
+    let dir = tree();
+    let (files, stats) = hash_all_files(&[dir.path()], &ScanOptions::default()).unwrap();
+    assert!(stats.errors.is_empty());
+    let listed: Vec<_> = files
+        .iter()
+        .map(|f| (f.path.strip_prefix(dir.path()).unwrap(), f.size))
+        .collect();
+    assert_eq!(listed.len(), 6);
+    assert_eq!(listed[5], (std::path::Path::new("unique.txt"), 5));
+    // a.txt and sub/b.txt
+    assert_eq!(files[0].hash, files[2].hash);
+    assert_ne!(files[0].hash, files[5].hash);
+}
+
+/// Hashes only the first byte of the content, so that unequal files collide.
+#[derive(Debug)]
+struct FirstByte;
+
+impl HashFunction for FirstByte {
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        Box::new(FirstByteHasher(None))
+    }
+}
+
+struct FirstByteHasher(Option<u8>);
+
+impl ContentHasher for FirstByteHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = self.0.or(data.first().copied());
+    }
+
+    fn finalize(self: Box<Self>) -> Hash {
+        Hash::new(HashAlgorithm::default(), &[self.0.unwrap_or_default()])
+    }
+}
+
+#[test]
+fn hash_function_can_be_replaced() {
+    let dir = tree();
+    create_files(dir.path(), &[("other.txt", "helps")]);
+    let options = ScanOptions {
+        hash_function: Some(std::sync::Arc::new(FirstByte)),
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        groups(dir.path(), &options)[1],
+        (
+            5,
+            vec![
+                "a.txt".into(),
+                "other.txt".into(),
+                "sub/b.txt".into(),
+                "sub/deeper/c.log".into()
+            ]
+        )
+    );
+}
+
+#[test]
+fn callback_receives_the_same_groups() {
+    let dir = tree();
+    let options = ScanOptions {
+        verify: true,
+        ..ScanOptions::default()
+    };
+    let mut found = Vec::new();
+    let stats = find_duplicates_with(&[dir.path()], &options, |group| {
+        found.push((group.size, group.paths))
+    })
+    .unwrap();
+    assert!(stats.errors.is_empty());
+    found.sort_by(|a, b| b.cmp(a));
+    let (duplicates, _) = find_duplicates(dir.path(), &options).unwrap();
+    let expected: Vec<_> = duplicates
+        .into_iter()
+        .map(|(_, info)| (info.size, info.paths))
+        .collect();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn normalize_text_ignores_line_endings_and_trailing_whitespace() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(
+        dir.path(),
+        &[
+            ("unix.txt", "first\nsecond\n"),
+            ("windows.txt", "first \r\nsecond\r\n\r\n"),
+            ("different.txt", "first\n second\n"),
+        ],
+    );
+    let options = ScanOptions {
+        normalize_text: true,
+        ..ScanOptions::default()
+    };
+    let (duplicates, stats) = find_duplicates(dir.path(), &options).unwrap();
+    assert_eq!(
+        relative_groups(dir.path(), &duplicates),
+        [(13, vec!["unix.txt".into(), "windows.txt".into()])]
+    );
+    assert!(stats.normalized.contains(&duplicates[0].0));
+}
+
+#[test]
+fn partial_hash_groups_likely_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(
+        dir.path(),
+        &[("a.bin", "head1234tail"), ("b.bin", "head5678tail")],
+    );
+    let options = ScanOptions {
+        partial_hash: Some(4),
+        ..ScanOptions::default()
+    };
+    let (duplicates, stats) = find_duplicates(dir.path(), &options).unwrap();
+    assert_eq!(
+        relative_groups(dir.path(), &duplicates),
+        [(12, vec!["a.bin".into(), "b.bin".into()])]
+    );
+    assert!(stats.likely.contains(&duplicates[0].0));
+    let options = ScanOptions {
+        verify: true,
+        ..options
+    };
+    assert!(groups(dir.path(), &options).is_empty());
+}
+
+#[test]
+fn manifest_replaces_the_reference_tree() {
+    let dir = tree();
+    let reference = tempfile::tempdir().unwrap();
+    create_files(reference.path(), &[("ref.bin", "0123456789"), ("x", "xyz")]);
+    let (manifest, _) = hash_all_files(&[reference.path()], &ScanOptions::default()).unwrap();
+    let ref_bin = reference.path().join("ref.bin");
+    drop(reference);
+    let (duplicates, stats) =
+        find_duplicates_of_manifest(&manifest, &[dir.path()], &ScanOptions::default()).unwrap();
+    assert!(stats.errors.is_empty());
+    assert_eq!(duplicates.len(), 1);
+    let paths = &duplicates[0].1.paths;
+    assert_eq!(paths.len(), 3);
+    assert!(paths.contains(&ref_bin));
+}
+
+#[test]
+fn report_is_sorted_and_stable_with_several_jobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let sizes = [919, 872, 496, 1577, 12, 3000, 64, 250];
+    for (i, size) in sizes.iter().enumerate() {
+        let content = format!("{i}").repeat(*size);
+        create_files(
+            dir.path(),
+            &[(&format!("a{i}"), &content), (&format!("b{i}"), &content)],
+        );
+    }
+    let report = || {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_duplicate_checker"))
+            .args(["-j", "8"])
+            .arg(dir.path())
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let first = report();
+    for _ in 0..4 {
+        assert_eq!(report(), first);
+    }
+    let reported: Vec<u64> = first
+        .lines()
+        .filter_map(|line| line.split("(filesize: ").nth(1))
+        .map(|size| size.split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    let mut expected = sizes.map(|size| size as u64).to_vec();
+    expected.sort_by(|a, b| b.cmp(a));
+    assert_eq!(reported, expected);
+}
@@ -0,0 +1,76 @@
+//! Benchmark of the scan pipeline over a synthetic directory tree.
+//!
+//! Run with `cargo test --release --test pipeline_benchmark -- --ignored --nocapture`
+//! to see the timings.
+
+use duplicate_checker::{find_duplicates, HashAlgorithm, ScanOptions};
+
+/// Writes `groups` sets of `copies` identical files plus as many unique files
+/// of the same size, spread over a few subdirectories.
+fn create_tree(root: &std::path::Path, groups: usize, copies: usize, size: usize) {
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut content = |buffer: &mut Vec<u8>| {
+        buffer.clear();
+        while buffer.len() < size {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            buffer.extend_from_slice(&seed.to_le_bytes());
+        }
+        buffer.truncate(size);
+    };
+    let mut buffer = Vec::new();
+    for group in 0..groups {
+        let dir = root.join(format!("dir{}", group % 8));
+        std::fs::create_dir_all(&dir).unwrap();
+        content(&mut buffer);
+        for copy in 0..copies {
+            std::fs::write(dir.join(format!("dup{group}_{copy}")), &buffer).unwrap();
+        }
+        content(&mut buffer);
+        std::fs::write(dir.join(format!("unique{group}")), &buffer).unwrap();
+    }
+}
+
+fn scan(root: &std::path::Path, jobs: usize) -> (Vec<String>, std::time::Duration) {
+    let options = ScanOptions {
+        jobs,
+        hash: HashAlgorithm::Blake3,
+        ..ScanOptions::default()
+    };
+    let start = std::time::Instant::now();
+    let (duplicates, _) = find_duplicates(root, &options).unwrap();
+    let elapsed = start.elapsed();
+    let groups = duplicates
+        .iter()
+        .map(|(hash, info)| format!("{hash} {} {:?}", info.size, info.paths))
+        .collect();
+    (groups, elapsed)
+}
+
+#[test]
+fn pipeline_matches_single_worker() {
+    let dir = tempfile::tempdir().unwrap();
+    create_tree(dir.path(), 16, 3, 8 * 1024);
+    let (single, _) = scan(dir.path(), 1);
+    let (parallel, _) = scan(dir.path(), 4);
+    assert_eq!(single.len(), 16);
+    assert_eq!(single, parallel);
+}
+
+#[test]
+#[ignore = "benchmark, run explicitly in release mode"]
+fn benchmark_pipeline() {
+    let dir = tempfile::tempdir().unwrap();
+    create_tree(dir.path(), 64, 3, 1024 * 1024);
+    let jobs = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let (single, single_time) = scan(dir.path(), 1);
+    let (parallel, parallel_time) = scan(dir.path(), jobs);
+    assert_eq!(single, parallel);
+    println!("single worker:       {single_time:.2?}");
+    println!("parallel ({jobs:>3} jobs): {parallel_time:.2?}");
+    println!(
+        "speedup:             {:.2}x",
+        single_time.as_secs_f64() / parallel_time.as_secs_f64()
+    );
+}